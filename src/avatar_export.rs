@@ -0,0 +1,441 @@
+//! Saves the current pose and expression of loaded avatars back to `.glb`/`.vrm` files.
+//!
+//! The source file is copied with the morph weights of its nodes replaced by the current ones.
+//! Like the other tools, this edits the JSON through [`GlbFile`] rather than `gltf::json` types,
+//! which would drop the VRM extension.
+//! Entities are matched to glTF nodes through the hierarchy, so nodes can share names.
+//! The current pose is either written into the node transforms, or added as a single-frame
+//! animation, leaving the rest pose untouched.
+//!
+//! Press F5 to export with the pose as rest pose, shift-F5 to export it as an animation.
+
+use crate::glb::{push, GlbFile};
+use crate::morph_targets::VrmData;
+use crate::vrm_gltf::VrmError;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use serde_json::{json, Map, Value};
+use std::path::{Path, PathBuf};
+
+const EXPORT_DIR: &str = "exports";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportPose {
+    /// Overwrite the node transforms.
+    Rest,
+    /// Keep the node transforms and add an animation holding the pose.
+    Animation,
+}
+
+/// The state of an avatar's scene, keyed by glTF node index.
+#[derive(Default)]
+pub struct AvatarState {
+    pub transforms: HashMap<usize, Transform>,
+    pub weights: HashMap<usize, Vec<f32>>,
+}
+
+/// Node indices of a JSON array like `scenes[0].nodes` or `nodes[0].children`.
+fn node_indices(nodes: &Value) -> Vec<usize> {
+    nodes
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|node| node.as_u64())
+        .map(|node| node as usize)
+        .collect()
+}
+
+impl AvatarState {
+    /// Collects the nodes of the first scene of `json` spawned below `root`.
+    pub fn from_scene(
+        root: Entity,
+        json: &Value,
+        children_query: &Query<&Children>,
+        node_query: &Query<(&Name, &Transform, Option<&MorphWeights>), Without<Handle<Mesh>>>,
+    ) -> Self {
+        let mut state = AvatarState::default();
+
+        // Node entities below an entity, in the order of the node's children.
+        let node_children = |entity: Entity| -> Vec<Entity> {
+            children_query
+                .get(entity)
+                .into_iter()
+                .flatten()
+                .copied()
+                .filter(|child| node_query.contains(*child))
+                .collect()
+        };
+
+        let names = node_names(json);
+        let scene_nodes = node_indices(&json["scenes"][0]["nodes"]);
+
+        // The entity the nodes of the scene were spawned below.
+        let spawns_scene = |entity: Entity| {
+            let children = node_children(entity);
+            let scene_names = scene_nodes.iter().map(|&node| names.get(node));
+            node_query
+                .iter_many(&children)
+                .map(|(name, _, _)| Some(name.as_str()))
+                .eq(scene_names.map(|name| name.map(String::as_str)))
+        };
+        let Some(scene_root) = std::iter::once(root)
+            .chain(children_query.iter_descendants(root))
+            .find(|entity| spawns_scene(*entity))
+        else {
+            return state;
+        };
+
+        let mut nodes: Vec<(Entity, usize)> = node_children(scene_root)
+            .into_iter()
+            .zip(scene_nodes)
+            .collect();
+
+        while let Some((entity, node)) = nodes.pop() {
+            let Ok((_, transform, weights)) = node_query.get(entity) else {
+                continue;
+            };
+
+            state.transforms.insert(node, *transform);
+            if let Some(weights) = weights {
+                state.weights.insert(node, weights.weights().to_vec());
+            }
+
+            let children = node_indices(&json["nodes"][node]["children"]);
+            nodes.extend(node_children(entity).into_iter().zip(children));
+        }
+
+        state
+    }
+}
+
+/// Names the glTF loader gives to nodes, by node index.
+pub fn node_names(json: &Value) -> Vec<String> {
+    json["nodes"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .enumerate()
+        .map(|(index, node)| match node["name"].as_str() {
+            Some(name) => name.to_string(),
+            None => format!("GltfNode{}", index),
+        })
+        .collect()
+}
+
+/// The JSON object at `index` of the top-level array `key`.
+fn object_mut<'a>(
+    json: &'a mut Value,
+    key: &str,
+    index: usize,
+) -> Option<&'a mut Map<String, Value>> {
+    json.get_mut(key)?.get_mut(index)?.as_object_mut()
+}
+
+fn node_mut(glb: &mut GlbFile, node: usize) -> Result<&mut Map<String, Value>, VrmError> {
+    object_mut(&mut glb.json, "nodes", node).ok_or_else(|| VrmError::InvalidIndex {
+        path: format!("nodes[{}]", node),
+    })
+}
+
+/// Returns the bytes of `bytes` (a `.glb`/`.vrm` file) with `state` applied.
+pub fn export_avatar(
+    bytes: &[u8],
+    state: &AvatarState,
+    pose: ExportPose,
+) -> Result<Vec<u8>, VrmError> {
+    let mut glb = GlbFile::from_slice(bytes)?;
+
+    // In node order, for a stable output.
+    let mut nodes: Vec<usize> = state
+        .transforms
+        .keys()
+        .chain(state.weights.keys())
+        .copied()
+        .collect();
+    nodes.sort_unstable();
+    nodes.dedup();
+
+    // Current morph weights become the default weights of the nodes, and of their meshes as
+    // long as the nodes sharing a mesh agree: Bevy's loader, for one, only reads the mesh's.
+    let mut mesh_weights: HashMap<usize, Vec<(usize, &Vec<f32>)>> = HashMap::default();
+
+    for &node in &nodes {
+        let Some(weights) = state.weights.get(&node) else {
+            continue;
+        };
+        let node_object = node_mut(&mut glb, node)?;
+        let Some(mesh) = node_object.get("mesh").and_then(Value::as_u64) else {
+            continue;
+        };
+
+        node_object.insert("weights".to_string(), json!(weights));
+        mesh_weights
+            .entry(mesh as usize)
+            .or_default()
+            .push((node, weights));
+    }
+
+    let mut meshes: Vec<_> = mesh_weights.into_iter().collect();
+    meshes.sort_unstable_by_key(|(mesh, _)| *mesh);
+
+    for (mesh, node_weights) in meshes {
+        let (node, weights) = node_weights[0];
+        let mesh_object =
+            object_mut(&mut glb.json, "meshes", mesh).ok_or_else(|| VrmError::InvalidIndex {
+                path: format!("nodes[{}].mesh", node),
+            })?;
+
+        if node_weights.iter().all(|(_, other)| *other == weights) {
+            mesh_object.insert("weights".to_string(), json!(weights));
+        } else {
+            let nodes: Vec<usize> = node_weights.iter().map(|(node, _)| *node).collect();
+            println!(
+                "Nodes {:?} share mesh {} with different morph weights, only their own weights \
+                 are exported, which some loaders ignore",
+                nodes, mesh
+            );
+        }
+    }
+
+    // Animated nodes must not have a matrix.
+    let mut animated = vec![];
+    for &node in &nodes {
+        let has_channels = state.transforms.contains_key(&node)
+            || state
+                .weights
+                .get(&node)
+                .is_some_and(|weights| !weights.is_empty());
+        if has_channels && !node_mut(&mut glb, node)?.contains_key("matrix") {
+            animated.push(node);
+        }
+    }
+
+    // An animation without channels isn't valid glTF.
+    let pose = if pose == ExportPose::Animation && animated.is_empty() {
+        println!("No node can hold the pose in an animation, exporting it as rest pose instead");
+        ExportPose::Rest
+    } else {
+        pose
+    };
+
+    match pose {
+        ExportPose::Rest => {
+            for &node in &nodes {
+                let Some(transform) = state.transforms.get(&node) else {
+                    continue;
+                };
+
+                let node = node_mut(&mut glb, node)?;
+                node.remove("matrix");
+                node.insert(
+                    "translation".to_string(),
+                    json!(transform.translation.to_array()),
+                );
+                node.insert("rotation".to_string(), json!(transform.rotation.to_array()));
+                node.insert("scale".to_string(), json!(transform.scale.to_array()));
+            }
+        }
+        ExportPose::Animation => {
+            let input = glb.push_accessor(&[0.0], "SCALAR", 1);
+
+            let mut channels = vec![];
+            let mut samplers = vec![];
+
+            let mut add_channel = |node: usize, path: &str, output: usize| {
+                channels.push(json!({
+                    "sampler": samplers.len(),
+                    "target": { "node": node, "path": path },
+                }));
+                samplers.push(json!({
+                    "input": input,
+                    "output": output,
+                    "interpolation": "STEP",
+                }));
+            };
+
+            for node in animated {
+                if let Some(transform) = state.transforms.get(&node) {
+                    let output = glb.push_accessor(&transform.translation.to_array(), "VEC3", 3);
+                    add_channel(node, "translation", output);
+                    let output = glb.push_accessor(&transform.rotation.to_array(), "VEC4", 4);
+                    add_channel(node, "rotation", output);
+                    let output = glb.push_accessor(&transform.scale.to_array(), "VEC3", 3);
+                    add_channel(node, "scale", output);
+                }
+
+                if let Some(weights) = state.weights.get(&node) {
+                    if !weights.is_empty() {
+                        let output = glb.push_accessor(weights, "SCALAR", 1);
+                        add_channel(node, "weights", output);
+                    }
+                }
+            }
+
+            push(
+                &mut glb.json,
+                "animations",
+                json!({
+                    "name": "Pose",
+                    "channels": channels,
+                    "samplers": samplers,
+                }),
+            );
+        }
+    }
+
+    glb.to_vec()
+}
+
+/// `exports/<name>_<suffix>.<extension>` for `models/<name>.<extension>`.
+pub fn export_path(source: &Path, suffix: &str) -> PathBuf {
+    let stem = source.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = source.extension().unwrap_or_default().to_string_lossy();
+
+    Path::new(EXPORT_DIR).join(format!("{}_{}.{}", stem, suffix, extension))
+}
+
+fn export_on_key(
+    input: Res<Input<KeyCode>>,
+    vrm_query: Query<(Entity, &VrmData)>,
+    children_query: Query<&Children>,
+    node_query: Query<(&Name, &Transform, Option<&MorphWeights>), Without<Handle<Mesh>>>,
+) {
+    if !input.just_pressed(KeyCode::F5) {
+        return;
+    }
+
+    let pose = if input.pressed(KeyCode::ShiftLeft) {
+        ExportPose::Animation
+    } else {
+        ExportPose::Rest
+    };
+
+    for (root, vrm) in &vrm_query {
+        let source = Path::new("assets").join(&vrm.path);
+        let destination = export_path(&source, "posed");
+
+        let result = std::fs::read(&source)
+            .map_err(VrmError::Io)
+            .and_then(|bytes| {
+                let json = GlbFile::from_slice(&bytes)?.json;
+                let state = AvatarState::from_scene(root, &json, &children_query, &node_query);
+                export_avatar(&bytes, &state, pose)
+            })
+            .and_then(|bytes| {
+                std::fs::create_dir_all(EXPORT_DIR)
+                    .and_then(|_| std::fs::write(&destination, bytes))
+                    .map_err(VrmError::Io)
+            });
+
+        match result {
+            Ok(_) => println!("Exported {:?} to {:?}", source, destination),
+            Err(error) => println!("Error exporting {:?}: {}", source, error),
+        }
+    }
+}
+
+pub struct AvatarExportPlugin;
+
+impl Plugin for AvatarExportPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, export_on_key);
+    }
+}
+
+#[test]
+fn test_export_avatar() {
+    let identity = [
+        1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0,
+    ];
+    let mut source = GlbFile {
+        json: json!({
+            "asset": { "version": "2.0" },
+            "meshes": [{ "primitives": [], "weights": [0.0, 0.0] }],
+            // Nodes sharing a name.
+            "nodes": [{ "name": "Face", "mesh": 0 }, { "name": "Face", "matrix": identity }, {}],
+        }),
+        bin: vec![],
+    };
+    let source = source.to_vec().unwrap();
+
+    assert_eq!(
+        node_names(&GlbFile::from_slice(&source).unwrap().json)[2],
+        "GltfNode2"
+    );
+
+    let mut state = AvatarState::default();
+    state
+        .transforms
+        .insert(0, Transform::from_xyz(1.0, 2.0, 3.0));
+    state.weights.insert(0, vec![0.5, 1.0]);
+
+    let rest = export_avatar(&source, &state, ExportPose::Rest).unwrap();
+    let rest = GlbFile::from_slice(&rest).unwrap();
+    assert_eq!(rest.json["nodes"][0]["translation"], json!([1.0, 2.0, 3.0]));
+    assert_eq!(
+        rest.json["nodes"][0]["rotation"],
+        json!([0.0, 0.0, 0.0, 1.0])
+    );
+    assert_eq!(rest.json["nodes"][1]["matrix"], json!(identity));
+    assert_eq!(rest.json["nodes"][0]["weights"], json!([0.5, 1.0]));
+    assert_eq!(rest.json["meshes"][0]["weights"], json!([0.5, 1.0]));
+
+    let animated = export_avatar(&source, &state, ExportPose::Animation).unwrap();
+    let animated = GlbFile::from_slice(&animated).unwrap();
+    assert!(animated.json["nodes"][0].get("translation").is_none());
+    assert_eq!(animated.json["meshes"][0]["weights"], json!([0.5, 1.0]));
+
+    let animation = &animated.json["animations"][0];
+    let paths: Vec<&str> = animation["channels"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|channel| channel["target"]["path"].as_str().unwrap())
+        .collect();
+    assert_eq!(paths, ["translation", "rotation", "scale", "weights"]);
+
+    let floats = |sampler: usize, key: &str| {
+        let accessor = animation["samplers"][sampler][key].as_u64().unwrap() as usize;
+        animated.read_floats(animated.float_accessor(accessor).unwrap())
+    };
+    assert_eq!(floats(0, "input"), vec![0.0]);
+    assert_eq!(floats(0, "output"), vec![1.0, 2.0, 3.0]);
+    assert_eq!(floats(3, "output"), vec![0.5, 1.0]);
+
+    // Only a node with a matrix to animate falls back to a rest export.
+    let mut matrix_state = AvatarState::default();
+    matrix_state
+        .transforms
+        .insert(1, Transform::from_xyz(1.0, 2.0, 3.0));
+    let fallback = export_avatar(&source, &matrix_state, ExportPose::Animation).unwrap();
+    let fallback = GlbFile::from_slice(&fallback).unwrap();
+    assert!(fallback.json.get("animations").is_none());
+    assert!(fallback.json["nodes"][1].get("matrix").is_none());
+    assert_eq!(
+        fallback.json["nodes"][1]["translation"],
+        json!([1.0, 2.0, 3.0])
+    );
+
+    // Nodes sharing a mesh with different weights keep their own, the mesh its defaults.
+    let mut shared = GlbFile::from_slice(&source).unwrap();
+    shared.json["nodes"][2]["mesh"] = json!(0);
+    let shared = shared.to_vec().unwrap();
+    let mut shared_state = AvatarState::default();
+    shared_state.weights.insert(0, vec![0.5, 1.0]);
+    shared_state.weights.insert(2, vec![1.0, 0.0]);
+    let exported = export_avatar(&shared, &shared_state, ExportPose::Rest).unwrap();
+    let exported = GlbFile::from_slice(&exported).unwrap();
+    assert_eq!(exported.json["nodes"][0]["weights"], json!([0.5, 1.0]));
+    assert_eq!(exported.json["nodes"][2]["weights"], json!([1.0, 0.0]));
+    assert_eq!(exported.json["meshes"][0]["weights"], json!([0.0, 0.0]));
+
+    // A mesh index past the meshes.
+    state.weights.insert(2, vec![1.0]);
+    let mut broken = GlbFile::from_slice(&source).unwrap();
+    broken.json["nodes"][2]["mesh"] = json!(4);
+    let broken = broken.to_vec().unwrap();
+    assert!(matches!(
+        export_avatar(&broken, &state, ExportPose::Rest),
+        Err(VrmError::InvalidIndex { .. })
+    ));
+}
@@ -0,0 +1,77 @@
+//! Analyses a speech clip once and writes its mouth shapes to a viseme timeline.
+//!
+//! ```bash
+//! cargo run --bin lip_sync_bake -- assets/sounds/sound.ogg assets/sounds/sound.visemes.json
+//! ```
+//!
+//! `--update-rate <hz>` and `--window-length <seconds>` change the analysis settings.
+
+use bevy_demo::viseme::{self, AnalysisSettings};
+use kira::sound::static_sound::{StaticSoundData, StaticSoundSettings};
+use std::process::ExitCode;
+
+const USAGE: &str = "Usage: lip_sync_bake [--update-rate <hz>] [--window-length <seconds>] <input.ogg> <output.visemes.json>";
+
+fn main() -> ExitCode {
+    let mut settings = AnalysisSettings::default();
+    let mut paths = vec![];
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let setting = match arg.as_str() {
+            "--update-rate" => &mut settings.update_rate,
+            "--window-length" => &mut settings.window_length,
+            _ => {
+                paths.push(arg);
+                continue;
+            }
+        };
+
+        match args.next().and_then(|value| value.parse().ok()) {
+            Some(value) if value > 0.0 => *setting = value,
+            _ => {
+                eprintln!("{} needs a positive number", arg);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    let [input, output] = paths.as_slice() else {
+        eprintln!("{}", USAGE);
+        return ExitCode::FAILURE;
+    };
+
+    let sound_data = match StaticSoundData::from_file(input, StaticSoundSettings::default()) {
+        Ok(data) => data,
+        Err(error) => {
+            eprintln!("Error reading {}: {:?}", input, error);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let samples = viseme::mono_samples(&sound_data);
+
+    let timeline = viseme::bake(&samples, sound_data.sample_rate, settings);
+
+    let json = match serde_json::to_string_pretty(&timeline) {
+        Ok(json) => json,
+        Err(error) => {
+            eprintln!("Error serializing timeline: {}", error);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if let Err(error) = std::fs::write(output, json) {
+        eprintln!("Error writing {}: {}", output, error);
+        return ExitCode::FAILURE;
+    }
+
+    println!(
+        "Baked {} visemes of {} to {}",
+        timeline.keys.len(),
+        input,
+        output
+    );
+
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,307 @@
+//! Lists the morph targets of a glTF or VRM model and checks the blend shape binds against them.
+//!
+//! ```bash
+//! cargo run --bin morph_inventory -- assets/models/AvatarSample_A.glb
+//! ```
+//!
+//! `--json` prints the report as JSON. Exits with an error if there are problems, e.g. VRM 0.x
+//! blend shape groups or VRM 1.0 expressions binding meshes, nodes or targets that don't exist.
+
+use bevy_demo::vrm_gltf::{json_chunk, VrmError};
+use serde::Serialize;
+use serde_json::Value;
+use std::process::ExitCode;
+
+const USAGE: &str = "Usage: morph_inventory [--json] <model.glb|model.vrm|model.gltf>";
+
+#[derive(Serialize, Debug, Default)]
+struct Inventory {
+    meshes: Vec<MeshInventory>,
+    problems: Vec<String>,
+}
+
+#[derive(Serialize, Debug, Default)]
+struct MeshInventory {
+    index: usize,
+    name: Option<String>,
+    /// Names of the nodes using the mesh, as the glTF loader names them.
+    nodes: Vec<String>,
+    /// From `extras.targetNames`, like the glTF loader.
+    target_names: Vec<String>,
+    /// Number of morph targets of each primitive.
+    primitive_targets: Vec<usize>,
+}
+
+impl MeshInventory {
+    fn target_count(&self) -> usize {
+        self.primitive_targets.iter().copied().max().unwrap_or(0)
+    }
+}
+
+fn array(value: &Value) -> &[Value] {
+    value.as_array().map(Vec::as_slice).unwrap_or_default()
+}
+
+/// Reads the JSON of a binary or plain glTF file.
+fn read_json(bytes: &[u8]) -> Result<Value, VrmError> {
+    let json = json_chunk(bytes)?;
+    serde_json::from_slice(&json).map_err(VrmError::Json)
+}
+
+fn inventory(json: &Value) -> Inventory {
+    let mut inventory = Inventory::default();
+
+    for (index, mesh) in array(&json["meshes"]).iter().enumerate() {
+        let mesh = MeshInventory {
+            index,
+            name: mesh["name"].as_str().map(str::to_string),
+            nodes: vec![],
+            target_names: array(&mesh["extras"]["targetNames"])
+                .iter()
+                .map(|name| name.as_str().unwrap_or_default().to_string())
+                .collect(),
+            primitive_targets: array(&mesh["primitives"])
+                .iter()
+                .map(|primitive| array(&primitive["targets"]).len())
+                .collect(),
+        };
+
+        let mesh_name = mesh.name.as_deref().unwrap_or("unnamed");
+        if mesh
+            .primitive_targets
+            .iter()
+            .any(|&count| count != mesh.target_count())
+        {
+            inventory.problems.push(format!(
+                "Mesh {} ({}) has primitives with different target counts: {:?}",
+                index, mesh_name, mesh.primitive_targets
+            ));
+        }
+        if !mesh.target_names.is_empty() && mesh.target_names.len() != mesh.target_count() {
+            inventory.problems.push(format!(
+                "Mesh {} ({}) has {} target names for {} targets",
+                index,
+                mesh_name,
+                mesh.target_names.len(),
+                mesh.target_count()
+            ));
+        }
+
+        inventory.meshes.push(mesh);
+    }
+
+    let nodes = array(&json["nodes"]);
+    for (index, node) in nodes.iter().enumerate() {
+        let Some(mesh) = node["mesh"].as_u64() else {
+            continue;
+        };
+        let name = match node["name"].as_str() {
+            Some(name) => name.to_string(),
+            None => format!("GltfNode{}", index),
+        };
+
+        match inventory.meshes.get_mut(mesh as usize) {
+            Some(mesh) => mesh.nodes.push(name),
+            None => inventory.problems.push(format!(
+                "Node {} ({}) refers to missing mesh {}",
+                index, name, mesh
+            )),
+        }
+    }
+
+    // VRM 0.x blend shape groups bind meshes.
+    let groups = &json["extensions"]["VRM"]["blendShapeMaster"]["blendShapeGroups"];
+    for group in array(groups) {
+        let group_name = group["name"].as_str().unwrap_or("unnamed");
+
+        for bind in array(&group["binds"]) {
+            let (Some(mesh), Some(target)) = (bind["mesh"].as_u64(), bind["index"].as_u64()) else {
+                inventory.problems.push(format!(
+                    "Blend shape group {} has an invalid bind",
+                    group_name
+                ));
+                continue;
+            };
+
+            match inventory.meshes.get(mesh as usize) {
+                None => inventory.problems.push(format!(
+                    "Blend shape group {} binds missing mesh {}",
+                    group_name, mesh
+                )),
+                Some(inventory_mesh) if target as usize >= inventory_mesh.target_count() => {
+                    let problem = format!(
+                        "Blend shape group {} binds target {} of mesh {}, which has {} targets",
+                        group_name,
+                        target,
+                        mesh,
+                        inventory_mesh.target_count()
+                    );
+                    inventory.problems.push(problem);
+                }
+                Some(_) => {}
+            }
+        }
+    }
+
+    // VRM 1.0 expressions bind nodes.
+    let expressions = &json["extensions"]["VRMC_vrm"]["expressions"];
+    for kind in ["preset", "custom"] {
+        let Some(expressions) = expressions[kind].as_object() else {
+            continue;
+        };
+
+        for (expression_name, expression) in expressions {
+            for bind in array(&expression["morphTargetBinds"]) {
+                let (Some(node), Some(target)) = (bind["node"].as_u64(), bind["index"].as_u64())
+                else {
+                    inventory.problems.push(format!(
+                        "Expression {} has an invalid bind",
+                        expression_name
+                    ));
+                    continue;
+                };
+
+                let mesh = nodes
+                    .get(node as usize)
+                    .and_then(|node| node["mesh"].as_u64())
+                    .and_then(|mesh| inventory.meshes.get(mesh as usize));
+
+                match mesh {
+                    None => inventory.problems.push(format!(
+                        "Expression {} binds node {}, which has no mesh",
+                        expression_name, node
+                    )),
+                    Some(mesh) if target as usize >= mesh.target_count() => {
+                        let problem = format!(
+                            "Expression {} binds target {} of node {}, which has {} targets",
+                            expression_name,
+                            target,
+                            node,
+                            mesh.target_count()
+                        );
+                        inventory.problems.push(problem);
+                    }
+                    Some(_) => {}
+                }
+            }
+        }
+    }
+
+    inventory
+}
+
+fn print_text(path: &str, inventory: &Inventory) {
+    println!("{}", path);
+
+    for mesh in &inventory.meshes {
+        if mesh.target_count() == 0 {
+            continue;
+        }
+
+        println!(
+            "\nMesh {} ({}), nodes [{}], {} primitives, {} targets",
+            mesh.index,
+            mesh.name.as_deref().unwrap_or("unnamed"),
+            mesh.nodes.join(", "),
+            mesh.primitive_targets.len(),
+            mesh.target_count()
+        );
+        for index in 0..mesh.target_count() {
+            let name = mesh
+                .target_names
+                .get(index)
+                .map_or("unnamed", String::as_str);
+            println!("  {:3} {}", index, name);
+        }
+    }
+
+    if inventory.problems.is_empty() {
+        println!("\nNo problems");
+    } else {
+        println!("\n{} problems:", inventory.problems.len());
+        for problem in &inventory.problems {
+            println!("  {}", problem);
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let mut as_json = false;
+    let mut paths = vec![];
+
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--json" => as_json = true,
+            _ => paths.push(arg),
+        }
+    }
+
+    let [path] = paths.as_slice() else {
+        eprintln!("{}", USAGE);
+        return ExitCode::FAILURE;
+    };
+
+    let json = match std::fs::read(path)
+        .map_err(|error| error.to_string())
+        .and_then(|bytes| read_json(&bytes).map_err(|error| error.to_string()))
+    {
+        Ok(json) => json,
+        Err(error) => {
+            eprintln!("Error reading {}: {}", path, error);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let inventory = inventory(&json);
+
+    if as_json {
+        match serde_json::to_string_pretty(&inventory) {
+            Ok(json) => println!("{}", json),
+            Err(error) => {
+                eprintln!("Error serializing inventory: {}", error);
+                return ExitCode::FAILURE;
+            }
+        }
+    } else {
+        print_text(path, &inventory);
+    }
+
+    if inventory.problems.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+#[test]
+fn test_inventory() {
+    let json = serde_json::json!({
+        "meshes": [{
+            "name": "Face",
+            "extras": { "targetNames": ["A", "I"] },
+            "primitives": [{ "targets": [{}, {}] }, { "targets": [{}, {}] }],
+        }],
+        "nodes": [{ "name": "Face", "mesh": 0 }, {}],
+        "extensions": {
+            "VRM": { "blendShapeMaster": { "blendShapeGroups": [
+                { "name": "A", "binds": [{ "mesh": 0, "index": 0 }] },
+                { "name": "O", "binds": [{ "mesh": 0, "index": 4 }, { "mesh": 2, "index": 0 }] },
+            ] } },
+            "VRMC_vrm": { "expressions": { "preset": {
+                "aa": { "morphTargetBinds": [{ "node": 1, "index": 0 }] },
+            } } },
+        },
+    });
+
+    let inventory = inventory(&json);
+    assert_eq!(inventory.meshes[0].nodes, vec!["Face"]);
+    assert_eq!(inventory.meshes[0].target_count(), 2);
+    assert_eq!(
+        inventory.problems,
+        vec![
+            "Blend shape group O binds target 4 of mesh 0, which has 2 targets",
+            "Blend shape group O binds missing mesh 2",
+            "Expression aa binds node 1, which has no mesh",
+        ]
+    );
+}
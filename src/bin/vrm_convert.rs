@@ -0,0 +1,41 @@
+//! Migrates a VRM 0.x model to VRM 1.0.
+//!
+//! ```bash
+//! cargo run --bin vrm_convert -- input.vrm output.vrm
+//! ```
+
+use bevy_demo::vrm_convert;
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().collect();
+    let [_, input, output] = args.as_slice() else {
+        eprintln!("Usage: vrm_convert <input.vrm> <output.vrm>");
+        return ExitCode::FAILURE;
+    };
+
+    let bytes = match std::fs::read(input) {
+        Ok(bytes) => bytes,
+        Err(error) => {
+            eprintln!("Error reading {}: {}", input, error);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let converted = match vrm_convert::convert_vrm0_to_vrm1(&bytes) {
+        Ok(converted) => converted,
+        Err(error) => {
+            eprintln!("Error converting {}: {}", input, error);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if let Err(error) = std::fs::write(output, converted) {
+        eprintln!("Error writing {}: {}", output, error);
+        return ExitCode::FAILURE;
+    }
+
+    println!("Converted {} to VRM 1.0 in {}", input, output);
+
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,306 @@
+//! Editing binary glTF files as plain JSON plus the binary chunk.
+//!
+//! Tools that rewrite models work on this rather than `gltf::json::Root`: with the `gltf` features
+//! the crate enables, the typed root drops extras, like mesh `targetNames`, and the extensions it
+//! doesn't know, like VRM's.
+
+use crate::vrm_gltf::VrmError;
+use serde_json::{json, Value};
+use std::borrow::Cow;
+
+pub const COMPONENT_TYPE_FLOAT: u64 = 5126;
+
+pub struct GlbFile {
+    pub json: Value,
+    /// Contents of the buffer with index 0.
+    pub bin: Vec<u8>,
+}
+
+/// Where the elements of an accessor of floats are in the binary chunk.
+#[derive(Clone, Copy, Debug)]
+pub struct AccessorLayout {
+    pub offset: usize,
+    pub stride: usize,
+    pub count: usize,
+    pub components: usize,
+}
+
+impl GlbFile {
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, VrmError> {
+        let glb = gltf::Glb::from_slice(bytes).map_err(VrmError::Gltf)?;
+        let json = serde_json::from_slice(&glb.json).map_err(VrmError::Json)?;
+        let bin = glb.bin.map(Cow::into_owned).unwrap_or_default();
+
+        Ok(GlbFile { json, bin })
+    }
+
+    pub fn to_vec(&mut self) -> Result<Vec<u8>, VrmError> {
+        if let Some(buffer) = self.json.pointer_mut("/buffers/0") {
+            buffer["byteLength"] = self.bin.len().into();
+        }
+
+        let json = serde_json::to_vec(&self.json).map_err(VrmError::Json)?;
+
+        let glb = gltf::Glb {
+            header: gltf::binary::Header {
+                magic: *b"glTF",
+                version: 2,
+                // Computed by the writer.
+                length: 0,
+            },
+            json: Cow::Owned(json),
+            bin: Some(Cow::Borrowed(&self.bin)),
+        };
+
+        glb.to_vec().map_err(VrmError::Gltf)
+    }
+
+    /// Returns `None` for accessors that aren't plain floats in the binary chunk,
+    /// e.g. sparse or normalized integer ones, or that don't fit in it.
+    pub fn float_accessor(&self, index: usize) -> Option<AccessorLayout> {
+        let accessor = self.json.pointer(&format!("/accessors/{}", index))?;
+
+        if accessor.get("sparse").is_some() {
+            return None;
+        }
+
+        self.layout(accessor)
+    }
+
+    /// Where the values in the buffer view of a float accessor are, ignoring `sparse`.
+    fn layout(&self, accessor: &Value) -> Option<AccessorLayout> {
+        if accessor["componentType"].as_u64()? != COMPONENT_TYPE_FLOAT {
+            return None;
+        }
+
+        let view = &self.json["bufferViews"][accessor["bufferView"].as_u64()? as usize];
+        if view["buffer"].as_u64()? != 0 {
+            return None;
+        }
+
+        let components = components(accessor)?;
+
+        let offset =
+            view["byteOffset"].as_u64().unwrap_or(0) + accessor["byteOffset"].as_u64().unwrap_or(0);
+        let stride = view["byteStride"].as_u64().unwrap_or(components as u64 * 4);
+
+        let layout = AccessorLayout {
+            offset: offset as usize,
+            stride: stride as usize,
+            count: accessor["count"].as_u64()? as usize,
+            components,
+        };
+
+        // Malformed accessors could point past the binary chunk.
+        let end = match layout.count {
+            0 => layout.offset,
+            count => layout
+                .stride
+                .checked_mul(count - 1)?
+                .checked_add(layout.offset)?
+                .checked_add(components * 4)?,
+        };
+        if end > self.bin.len() {
+            return None;
+        }
+
+        Some(layout)
+    }
+
+    /// Turns a sparse float accessor into a plain one, with the substituted values written to a new
+    /// buffer view. Returns `None` if the accessor isn't a float one or doesn't fit in the binary
+    /// chunk, leaving it as is.
+    pub fn densify_accessor(&mut self, index: usize) -> Option<()> {
+        let accessor = self.json.pointer(&format!("/accessors/{}", index))?;
+        let sparse = accessor.get("sparse")?;
+        let components = components(accessor)?;
+        let count = accessor["count"].as_u64()? as usize;
+
+        // Without a buffer view, the values that aren't substituted are zeros.
+        let mut floats = match accessor.get("bufferView") {
+            Some(_) => {
+                let layout = self.layout(accessor)?;
+                self.read_floats(layout)
+            }
+            None => {
+                if accessor["componentType"].as_u64()? != COMPONENT_TYPE_FLOAT {
+                    return None;
+                }
+                vec![0.0; count.checked_mul(components)?]
+            }
+        };
+
+        let substitutions = sparse["count"].as_u64()? as usize;
+        let indices = &sparse["indices"];
+        let index_size = match indices["componentType"].as_u64()? {
+            5121 => 1,
+            5123 => 2,
+            5125 => 4,
+            _ => return None,
+        };
+        let indices = self.view_bytes(indices, substitutions.checked_mul(index_size)?)?;
+        let values = self.view_bytes(
+            &sparse["values"],
+            substitutions.checked_mul(components * 4)?,
+        )?;
+
+        for (index, value) in indices
+            .chunks_exact(index_size)
+            .zip(values.chunks_exact(components * 4))
+        {
+            let mut bytes = [0; 4];
+            bytes[..index_size].copy_from_slice(index);
+            let element = u32::from_le_bytes(bytes) as usize;
+
+            let element = floats.get_mut(element * components..(element + 1) * components)?;
+            for (float, bytes) in element.iter_mut().zip(value.chunks_exact(4)) {
+                *float = f32::from_le_bytes(bytes.try_into().unwrap());
+            }
+        }
+
+        let view = self.push_floats(&floats);
+
+        let accessor = self
+            .json
+            .pointer_mut(&format!("/accessors/{}", index))?
+            .as_object_mut()?;
+        accessor.remove("sparse");
+        accessor.remove("byteOffset");
+        accessor.insert("bufferView".to_string(), view.into());
+
+        Some(())
+    }
+
+    /// `len` bytes at the buffer view and byte offset of a sparse `indices` or `values` object.
+    fn view_bytes(&self, sparse: &Value, len: usize) -> Option<&[u8]> {
+        let view = &self.json["bufferViews"][sparse["bufferView"].as_u64()? as usize];
+        if view["buffer"].as_u64()? != 0 {
+            return None;
+        }
+
+        let offset =
+            view["byteOffset"].as_u64().unwrap_or(0) + sparse["byteOffset"].as_u64().unwrap_or(0);
+        let start = offset as usize;
+        self.bin.get(start..start.checked_add(len)?)
+    }
+
+    pub fn read_floats(&self, layout: AccessorLayout) -> Vec<f32> {
+        let mut floats = Vec::with_capacity(layout.count * layout.components);
+
+        for element in 0..layout.count {
+            let start = layout.offset + element * layout.stride;
+            for component in 0..layout.components {
+                let at = start + component * 4;
+                floats.push(f32::from_le_bytes(self.bin[at..at + 4].try_into().unwrap()));
+            }
+        }
+
+        floats
+    }
+
+    /// Calls `f` with the components of every element of the accessor and writes them back.
+    pub fn map_floats(&mut self, layout: AccessorLayout, mut f: impl FnMut(&mut [f32])) {
+        let mut element = vec![0.0; layout.components];
+
+        for index in 0..layout.count {
+            let start = layout.offset + index * layout.stride;
+
+            for (component, value) in element.iter_mut().enumerate() {
+                let at = start + component * 4;
+                *value = f32::from_le_bytes(self.bin[at..at + 4].try_into().unwrap());
+            }
+
+            f(&mut element);
+
+            for (component, value) in element.iter().enumerate() {
+                let at = start + component * 4;
+                self.bin[at..at + 4].copy_from_slice(&value.to_le_bytes());
+            }
+        }
+    }
+
+    /// Appends floats to the binary chunk and returns the index of a new buffer view of them.
+    fn push_floats(&mut self, floats: &[f32]) -> usize {
+        if self.json.pointer("/buffers/0").is_none() {
+            push(&mut self.json, "buffers", json!({ "byteLength": 0 }));
+        }
+
+        // Floats must be aligned to 4 bytes.
+        while self.bin.len() % 4 != 0 {
+            self.bin.push(0);
+        }
+
+        let offset = self.bin.len();
+        for value in floats {
+            self.bin.extend_from_slice(&value.to_le_bytes());
+        }
+
+        push(
+            &mut self.json,
+            "bufferViews",
+            json!({
+                "buffer": 0,
+                "byteOffset": offset,
+                "byteLength": floats.len() * 4,
+            }),
+        )
+    }
+
+    /// Appends floats to the binary chunk and returns the index of a new accessor for them.
+    /// `accessor_type` is e.g. `"VEC3"`, holding `components` floats per element.
+    pub fn push_accessor(
+        &mut self,
+        floats: &[f32],
+        accessor_type: &str,
+        components: usize,
+    ) -> usize {
+        let view = self.push_floats(floats);
+
+        // Bounds are only mandatory for some accessors, but cheap to always have.
+        let mut min = vec![f32::MAX; components];
+        let mut max = vec![f32::MIN; components];
+        for element in floats.chunks(components) {
+            for (component, value) in element.iter().enumerate() {
+                min[component] = min[component].min(*value);
+                max[component] = max[component].max(*value);
+            }
+        }
+
+        push(
+            &mut self.json,
+            "accessors",
+            json!({
+                "bufferView": view,
+                "componentType": COMPONENT_TYPE_FLOAT,
+                "count": floats.len() / components,
+                "type": accessor_type,
+                "min": min,
+                "max": max,
+            }),
+        )
+    }
+}
+
+/// Components of an element of `accessor`, by its type.
+fn components(accessor: &Value) -> Option<usize> {
+    match accessor["type"].as_str()? {
+        "SCALAR" => Some(1),
+        "VEC2" => Some(2),
+        "VEC3" => Some(3),
+        "VEC4" | "MAT2" => Some(4),
+        "MAT3" => Some(9),
+        "MAT4" => Some(16),
+        _ => None,
+    }
+}
+
+/// Appends to the top-level array `key`, creating it if needed, and returns the new index.
+pub fn push(json: &mut Value, key: &str, value: Value) -> usize {
+    if !json[key].is_array() {
+        json[key] = Value::Array(vec![]);
+    }
+
+    let array = json[key].as_array_mut().unwrap();
+    array.push(value);
+    array.len() - 1
+}
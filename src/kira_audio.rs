@@ -0,0 +1,89 @@
+//! Audio through kira, replacing `bevy_audio`.
+//!
+//! Clips are decoded once when loaded as [`AudioClip`] assets. The same decoded frames are played
+//! by the [`AudioManager`] and analysed for lip sync, so both follow one clock.
+
+use crate::viseme;
+use bevy::asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext};
+use bevy::prelude::*;
+use bevy::utils::BoxedFuture;
+use kira::manager::{backend::DefaultBackend, AudioManager, AudioManagerSettings};
+use kira::sound::static_sound::{StaticSoundData, StaticSoundSettings};
+use std::error::Error;
+use std::io::Cursor;
+use std::sync::Arc;
+
+#[derive(Asset, TypePath, Clone)]
+pub struct AudioClip {
+    pub sound_data: StaticSoundData,
+    /// All channels mixed down, for analysis.
+    pub samples: Arc<[f32]>,
+}
+
+impl AudioClip {
+    pub fn new(sound_data: StaticSoundData) -> Self {
+        AudioClip {
+            samples: viseme::mono_samples(&sound_data).into(),
+            sound_data,
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sound_data.sample_rate
+    }
+
+    /// In seconds.
+    pub fn duration(&self) -> f64 {
+        self.sound_data.duration().as_secs_f64()
+    }
+}
+
+#[derive(Default)]
+struct AudioClipLoader;
+
+impl AssetLoader for AudioClipLoader {
+    type Asset = AudioClip;
+    type Settings = ();
+    type Error = Box<dyn Error + Send + Sync>;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<AudioClip, Self::Error>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+
+            let sound_data =
+                StaticSoundData::from_cursor(Cursor::new(bytes), StaticSoundSettings::default())
+                    .map_err(|error| format!("Error decoding audio: {:?}", error))?;
+
+            Ok(AudioClip::new(sound_data))
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["ogg", "oga", "wav", "flac", "mp3"]
+    }
+}
+
+/// Adds the [`AudioManager`] as a non-send resource, if there's an audio device.
+pub struct KiraAudioPlugin;
+
+impl Plugin for KiraAudioPlugin {
+    fn build(&self, app: &mut App) {
+        match AudioManager::<DefaultBackend>::new(AudioManagerSettings::default()) {
+            Ok(manager) => {
+                app.insert_non_send_resource(manager);
+            }
+            Err(error) => {
+                println!("Error creating audio manager: {:?}", error);
+            }
+        }
+
+        app.init_asset::<AudioClip>()
+            .register_asset_loader(AudioClipLoader);
+    }
+}
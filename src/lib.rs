@@ -0,0 +1,9 @@
+//! Modules shared by the viewer and the command line tools in `src/bin`.
+
+pub mod glb;
+pub mod viseme;
+pub mod vrm1_gltf;
+pub mod vrm_convert;
+pub mod vrm_gltf;
+
+pub use gltf::json as gltf_json;
@@ -0,0 +1,437 @@
+//! Lip sync for entities speaking audio clips.
+//!
+//! Add a [`LipSyncSource`] and a [`MouthShape`] to an entity, then queue audio clips on the source.
+//! Clips are played one after another through kira, and the mouth shapes estimated at the
+//! current playback position are blended into the [`MouthShape`].
+//!
+//! Clips can come with a [`BakedVisemes`] timeline made by the `lip_sync_bake` tool,
+//! which is played back instead of analysing the audio while it plays. Phoneme timing files
+//! (`.phonemes.json`, Rhubarb `.tsv` or Papagayo `.dat`) load as [`BakedVisemes`] too.
+
+use crate::kira_audio::AudioClip;
+use crate::phoneme::{self, PhonemeMap};
+use crate::viseme::{Analyser, AnalysisSettings, VisemeBlender, VisemeTimeline};
+use bevy::asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext, LoadState};
+use bevy::prelude::*;
+use bevy::utils::BoxedFuture;
+use kira::{
+    manager::AudioManager,
+    sound::static_sound::{StaticSoundHandle, StaticSoundSettings},
+    sound::PlaybackState,
+    tween::Tween,
+};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::sync::Arc;
+
+/// Weights of the A, I, U, E and O mouth shapes, in the range of 0 to 1.
+#[derive(Component, Default, Clone, Copy, Debug, PartialEq)]
+pub struct MouthShape(pub [f32; 5]);
+
+/// A [`VisemeTimeline`] loaded from a `.visemes.json` file.
+#[derive(Asset, TypePath, Clone, Debug)]
+pub struct BakedVisemes(pub VisemeTimeline);
+
+#[derive(Default)]
+struct BakedVisemesLoader;
+
+impl AssetLoader for BakedVisemesLoader {
+    type Asset = BakedVisemes;
+    type Settings = ();
+    type Error = Box<dyn Error + Send + Sync>;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<BakedVisemes, Self::Error>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+            Ok(BakedVisemes(serde_json::from_slice(&bytes)?))
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["visemes.json"]
+    }
+}
+
+/// Maps phoneme timings to visemes, as an alternative to analysing the audio.
+#[derive(Default)]
+struct PhonemeTimingsLoader;
+
+impl AssetLoader for PhonemeTimingsLoader {
+    type Asset = BakedVisemes;
+    type Settings = ();
+    type Error = Box<dyn Error + Send + Sync>;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<BakedVisemes, Self::Error>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+
+            let (timings, map) = if load_context.path().to_string_lossy().ends_with(".json") {
+                (phoneme::parse_json(&bytes)?, PhonemeMap::arpabet())
+            } else {
+                phoneme::parse_tsv(std::str::from_utf8(&bytes)?)?
+            };
+
+            Ok(BakedVisemes(map.timeline(&timings)))
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["phonemes.json", "tsv", "dat"]
+    }
+}
+
+/// Speaks queued audio clips.
+#[derive(Component)]
+pub struct LipSyncSource {
+    /// Used for clips without baked visemes.
+    pub settings: AnalysisSettings,
+    /// Smooths the estimates into the [`MouthShape`].
+    pub blender: VisemeBlender,
+    queue: VecDeque<QueuedClip>,
+    speech: Option<Speech>,
+    /// The clip started last, for replaying it.
+    last_clip: Option<QueuedClip>,
+    looping: bool,
+    /// As amplitude.
+    volume: f64,
+}
+
+impl Default for LipSyncSource {
+    fn default() -> Self {
+        LipSyncSource {
+            settings: AnalysisSettings::default(),
+            blender: VisemeBlender::default(),
+            queue: VecDeque::new(),
+            speech: None,
+            last_clip: None,
+            looping: false,
+            volume: 1.0,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct QueuedClip {
+    audio: Handle<AudioClip>,
+    visemes: Option<Handle<BakedVisemes>>,
+}
+
+/// A clip that is being played.
+struct Speech {
+    /// All channels mixed down.
+    samples: Arc<[f32]>,
+    sample_rate: u32,
+    /// In seconds.
+    duration: f64,
+    handle: StaticSoundHandle,
+    mouth: MouthSource,
+}
+
+enum MouthSource {
+    Analysis(Analyser),
+    Baked {
+        timeline: VisemeTimeline,
+        /// Index of the key last given to the blender.
+        key: Option<usize>,
+    },
+}
+
+impl LipSyncSource {
+    /// Speaks `clip` after the clips queued before.
+    pub fn queue(&mut self, clip: Handle<AudioClip>) {
+        self.queue.push_back(QueuedClip {
+            audio: clip,
+            visemes: None,
+        });
+    }
+
+    /// Like [`LipSyncSource::queue`], with the mouth shapes baked ahead of time
+    /// or taken from phoneme timings.
+    pub fn queue_baked(&mut self, clip: Handle<AudioClip>, visemes: Handle<BakedVisemes>) {
+        self.queue.push_back(QueuedClip {
+            audio: clip,
+            visemes: Some(visemes),
+        });
+    }
+
+    /// Stops the current clip, drops the queue and speaks `clip`.
+    pub fn interrupt(&mut self, clip: Handle<AudioClip>) {
+        self.stop();
+        self.queue(clip);
+    }
+
+    /// Makes `clip` the one [`LipSyncSource::replay`] speaks, without speaking it.
+    pub fn cue(&mut self, clip: Handle<AudioClip>) {
+        self.last_clip = Some(QueuedClip {
+            audio: clip,
+            visemes: None,
+        });
+    }
+
+    /// Stops the current clip and drops the queue, releasing the mouth.
+    pub fn stop(&mut self) {
+        self.queue.clear();
+
+        if let Some(mut speech) = self.speech.take() {
+            report(speech.handle.stop(Tween::default()), "stopping");
+        }
+        self.blender.set_target(None, 0.0);
+    }
+
+    /// Speaks the clip started last again, if nothing else is queued.
+    pub fn replay(&mut self) {
+        if self.is_speaking() {
+            return;
+        }
+
+        if let Some(clip) = self.last_clip.clone() {
+            self.queue.push_back(clip);
+        }
+    }
+
+    /// The clip [`replay`](Self::replay) plays.
+    pub fn last_clip(&self) -> Option<&Handle<AudioClip>> {
+        self.last_clip.as_ref().map(|clip| &clip.audio)
+    }
+
+    pub fn pause(&mut self) {
+        if let Some(speech) = self.speech.as_mut() {
+            report(speech.handle.pause(Tween::default()), "pausing");
+        }
+    }
+
+    pub fn resume(&mut self) {
+        if let Some(speech) = self.speech.as_mut() {
+            report(speech.handle.resume(Tween::default()), "resuming");
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.speech.as_ref().is_some_and(|speech| {
+            matches!(
+                speech.handle.state(),
+                PlaybackState::Pausing | PlaybackState::Paused
+            )
+        })
+    }
+
+    pub fn toggle_pause(&mut self) {
+        if self.is_paused() {
+            self.resume();
+        } else {
+            self.pause();
+        }
+    }
+
+    /// Moves the current clip to `position` seconds.
+    pub fn seek(&mut self, position: f64) {
+        if let Some(speech) = self.speech.as_mut() {
+            let position = position.clamp(0.0, speech.duration);
+            report(speech.handle.seek_to(position), "seeking");
+        }
+    }
+
+    /// Playback position of the current clip, in seconds.
+    pub fn position(&self) -> Option<f64> {
+        self.speech.as_ref().map(|speech| speech.handle.position())
+    }
+
+    /// Duration of the current clip, in seconds.
+    pub fn duration(&self) -> Option<f64> {
+        self.speech.as_ref().map(|speech| speech.duration)
+    }
+
+    /// Whether clips loop until stopped.
+    pub fn set_looping(&mut self, looping: bool) {
+        self.looping = looping;
+
+        if let Some(speech) = self.speech.as_mut() {
+            let result = if looping {
+                speech.handle.set_loop_region(..)
+            } else {
+                speech.handle.set_loop_region(None)
+            };
+            report(result, "changing the loop of");
+        }
+    }
+
+    pub fn is_looping(&self) -> bool {
+        self.looping
+    }
+
+    /// `volume` as amplitude, 1 keeping the clip as is.
+    pub fn set_volume(&mut self, volume: f64) {
+        self.volume = volume;
+
+        if let Some(speech) = self.speech.as_mut() {
+            report(
+                speech.handle.set_volume(volume, Tween::default()),
+                "changing the volume of",
+            );
+        }
+    }
+
+    pub fn volume(&self) -> f64 {
+        self.volume
+    }
+
+    /// Whether a clip is playing, paused or queued.
+    pub fn is_speaking(&self) -> bool {
+        self.speech.is_some() || !self.queue.is_empty()
+    }
+}
+
+fn report<E: std::fmt::Debug>(result: Result<(), E>, action: &str) {
+    if let Err(error) = result {
+        println!("Error {} speech: {:?}", action, error);
+    }
+}
+
+/// Starts the next queued clip of idle sources once it's loaded.
+fn start_speech(
+    manager: Option<NonSendMut<AudioManager>>,
+    asset_server: Res<AssetServer>,
+    audio_clips: Res<Assets<AudioClip>>,
+    baked_visemes: Res<Assets<BakedVisemes>>,
+    mut sources: Query<&mut LipSyncSource>,
+) {
+    let Some(mut manager) = manager else {
+        return;
+    };
+
+    for mut source in &mut sources {
+        if source.speech.is_some() {
+            continue;
+        }
+
+        let Some(clip) = source.queue.front() else {
+            continue;
+        };
+
+        let failed = |handle: UntypedHandle| {
+            let failed = asset_server.load_state(&handle) == LoadState::Failed;
+            if failed {
+                println!("Error loading {:?}", handle.path());
+            }
+            failed
+        };
+
+        if failed(clip.audio.clone().untyped())
+            || clip
+                .visemes
+                .as_ref()
+                .is_some_and(|v| failed(v.clone().untyped()))
+        {
+            source.queue.pop_front();
+            continue;
+        }
+
+        let Some(audio_clip) = audio_clips.get(&clip.audio) else {
+            continue;
+        };
+
+        let mouth = match &clip.visemes {
+            Some(visemes) => match baked_visemes.get(visemes) {
+                Some(visemes) => MouthSource::Baked {
+                    timeline: visemes.0.clone(),
+                    key: None,
+                },
+                None => continue,
+            },
+            None => MouthSource::Analysis(Analyser::new(source.settings)),
+        };
+
+        let clip = source.queue.pop_front().unwrap();
+
+        let mut settings = StaticSoundSettings::new().volume(source.volume);
+        if source.looping {
+            settings = settings.loop_region(..);
+        }
+
+        match manager.play(audio_clip.sound_data.with_settings(settings)) {
+            Ok(handle) => {
+                source.last_clip = Some(clip);
+                source.speech = Some(Speech {
+                    samples: audio_clip.samples.clone(),
+                    sample_rate: audio_clip.sample_rate(),
+                    duration: audio_clip.duration(),
+                    handle,
+                    mouth,
+                });
+            }
+            Err(error) => {
+                println!("Playing speech failed: {:?}", error);
+            }
+        }
+    }
+}
+
+fn update_mouth_shapes(time: Res<Time>, mut sources: Query<(&mut LipSyncSource, &mut MouthShape)>) {
+    for (mut source, mut mouth) in &mut sources {
+        let source = source.as_mut();
+
+        if let Some(speech) = source.speech.as_mut() {
+            match speech.handle.state() {
+                PlaybackState::Playing => {
+                    // The position of the audio clock, so pauses, seeks and hitches can't desync the mouth.
+                    let current_time = speech.handle.position();
+
+                    match &mut speech.mouth {
+                        MouthSource::Baked { timeline, key } => {
+                            let current_key = timeline.key_at(current_time);
+                            if current_key != *key {
+                                *key = current_key;
+                                match current_key.map(|index| &timeline.keys[index]) {
+                                    Some(k) => source.blender.set_target(Some(k.vowel), k.amount),
+                                    None => source.blender.set_target(None, 0.0),
+                                }
+                            }
+                        }
+                        MouthSource::Analysis(analyser) => {
+                            let estimate =
+                                analyser.update(&speech.samples, speech.sample_rate, current_time);
+                            if let Some((vowel, amount)) = estimate {
+                                source.blender.set_target(Some(vowel), amount);
+                            }
+                        }
+                    }
+                }
+                // Hold the mouth where it is until playback resumes.
+                PlaybackState::Pausing | PlaybackState::Paused => {
+                    continue;
+                }
+                PlaybackState::Stopping | PlaybackState::Stopped => {
+                    source.speech = None;
+                    source.blender.set_target(None, 0.0);
+                }
+            }
+        } else if source.blender.is_settled() {
+            continue;
+        }
+
+        mouth.set_if_neq(MouthShape(source.blender.update(time.delta_seconds())));
+    }
+}
+
+pub struct LipSyncPlugin;
+
+impl Plugin for LipSyncPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<BakedVisemes>()
+            .register_asset_loader(BakedVisemesLoader)
+            .register_asset_loader(PhonemeTimingsLoader)
+            .add_systems(Update, (start_speech, update_mouth_shapes).chain());
+    }
+}
@@ -0,0 +1,259 @@
+//! Lip sync from live audio, e.g. text to speech generated on the fly.
+//!
+//! PCM chunks pushed through a [`StreamWriter`], from any thread, go into a ring buffer, and the
+//! most recent window of it is analysed into the [`MouthShape`] of the entity with the
+//! [`LipSyncStream`]. The audio itself isn't played.
+//!
+//! [`StreamFile`] stands in for a live stream by pushing an [`AudioClip`] in real time. Insert a
+//! [`StreamClip`] to stream a clip once it's loaded; both components go away once it has been
+//! streamed and the mouth closed.
+
+use crate::kira_audio::AudioClip;
+use crate::lip_sync::MouthShape;
+use crate::viseme::{Analyser, AnalysisSettings, SampleRing, VisemeBlender};
+use bevy::asset::LoadState;
+use bevy::prelude::*;
+use std::sync::{Arc, Mutex};
+
+/// Pushes interleaved PCM samples to a [`LipSyncStream`].
+#[derive(Clone)]
+pub struct StreamWriter {
+    ring: Arc<Mutex<SampleRing>>,
+}
+
+impl StreamWriter {
+    pub fn push(&self, chunk: &[f32]) {
+        match self.ring.lock() {
+            Ok(mut ring) => ring.push(chunk),
+            Err(error) => println!("Error pushing to lip sync stream: {}", error),
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct LipSyncStream {
+    /// Smooths the estimates into the [`MouthShape`].
+    pub blender: VisemeBlender,
+    writer: StreamWriter,
+    analyser: Analyser,
+    /// Stream time at the last update, in seconds.
+    last_stream_time: f64,
+    /// How long no samples arrived, in seconds.
+    starved_for: f32,
+}
+
+impl LipSyncStream {
+    /// A stream of `channels` interleaved channels at `sample_rate`.
+    pub fn new(sample_rate: u32, channels: usize, settings: AnalysisSettings) -> Self {
+        // A bit more than a window, so chunks can arrive late.
+        let ring = SampleRing::new(sample_rate, channels, settings.window_length * 2.0);
+
+        LipSyncStream {
+            blender: VisemeBlender::default(),
+            writer: StreamWriter {
+                ring: Arc::new(Mutex::new(ring)),
+            },
+            analyser: Analyser::new(settings),
+            last_stream_time: 0.0,
+            starved_for: 0.0,
+        }
+    }
+
+    pub fn writer(&self) -> StreamWriter {
+        self.writer.clone()
+    }
+}
+
+/// Pushes a file to a stream as if it arrived live.
+#[derive(Component)]
+pub struct StreamFile {
+    clip: AudioClip,
+    writer: StreamWriter,
+    /// In samples.
+    position: usize,
+    /// Part of a sample that was due but not pushed yet, so the stream keeps up with real time.
+    remainder: f64,
+}
+
+impl StreamFile {
+    /// Creates a [`LipSyncStream`] too, to be spawned alongside.
+    pub fn new(clip: AudioClip, settings: AnalysisSettings) -> (Self, LipSyncStream) {
+        let stream = LipSyncStream::new(clip.sample_rate(), 1, settings);
+
+        let file = StreamFile {
+            clip,
+            writer: stream.writer(),
+            position: 0,
+            remainder: 0.0,
+        };
+
+        (file, stream)
+    }
+}
+
+/// Streams a clip into the [`MouthShape`] of this entity once the clip is loaded.
+#[derive(Component)]
+pub struct StreamClip(pub Handle<AudioClip>);
+
+fn start_stream_files(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    audio_clips: Res<Assets<AudioClip>>,
+    requests: Query<(Entity, &StreamClip)>,
+) {
+    for (entity, request) in &requests {
+        if asset_server.load_state(&request.0) == LoadState::Failed {
+            println!("Error loading {:?}", request.0.path());
+            commands.entity(entity).remove::<StreamClip>();
+            continue;
+        }
+
+        let Some(clip) = audio_clips.get(&request.0) else {
+            continue;
+        };
+
+        let (file, stream) = StreamFile::new(clip.clone(), AnalysisSettings::default());
+        commands
+            .entity(entity)
+            .remove::<StreamClip>()
+            .insert((file, stream));
+    }
+}
+
+/// Removes streams of files once the whole file was pushed and the mouth has closed.
+fn finish_stream_files(
+    mut commands: Commands,
+    streams: Query<(Entity, &StreamFile, &LipSyncStream)>,
+) {
+    for (entity, file, stream) in &streams {
+        let closed = stream.starved_for as f64 > stream.analyser.settings().window_length
+            && stream.blender.is_settled();
+
+        if file.position >= file.clip.samples.len() && closed {
+            commands
+                .entity(entity)
+                .remove::<(StreamFile, LipSyncStream)>();
+        }
+    }
+}
+
+fn feed_stream_files(time: Res<Time>, mut files: Query<&mut StreamFile>) {
+    for mut file in &mut files {
+        let due = time.delta_seconds_f64() * file.clip.sample_rate() as f64 + file.remainder;
+        let count = due as usize;
+        file.remainder = due - count as f64;
+
+        let end = (file.position + count).min(file.clip.samples.len());
+
+        file.writer.push(&file.clip.samples[file.position..end]);
+        file.position = end;
+    }
+}
+
+fn update_stream_mouth_shapes(
+    time: Res<Time>,
+    mut streams: Query<(&mut LipSyncStream, &mut MouthShape)>,
+) {
+    for (mut stream, mut mouth) in &mut streams {
+        let stream = stream.as_mut();
+
+        let estimate = {
+            let Ok(mut ring) = stream.writer.ring.lock() else {
+                continue;
+            };
+
+            let stream_time = ring.time();
+            if stream_time > stream.last_stream_time {
+                stream.starved_for = 0.0;
+            } else {
+                stream.starved_for += time.delta_seconds();
+            }
+            stream.last_stream_time = stream_time;
+
+            let sample_rate = ring.sample_rate();
+            stream
+                .analyser
+                .update_latest(ring.latest(), sample_rate, stream_time)
+        };
+
+        if let Some((vowel, amount)) = estimate {
+            stream.blender.set_target(Some(vowel), amount);
+        }
+
+        // Close the mouth once the window only holds old audio.
+        if stream.starved_for as f64 > stream.analyser.settings().window_length {
+            stream.blender.set_target(None, 0.0);
+        }
+
+        mouth.set_if_neq(MouthShape(stream.blender.update(time.delta_seconds())));
+    }
+}
+
+pub struct LipSyncStreamPlugin;
+
+impl Plugin for LipSyncStreamPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                start_stream_files,
+                feed_stream_files,
+                update_stream_mouth_shapes,
+                finish_stream_files,
+            )
+                .chain(),
+        );
+    }
+}
+
+#[test]
+fn test_stream_mouth_shape() {
+    use crate::viseme;
+    use bevy::ecs::system::RunSystemOnce;
+    use kira::sound::static_sound::{StaticSoundData, StaticSoundSettings};
+    use std::path::Path;
+    use std::time::Duration;
+
+    /// Frame time, in seconds.
+    const DELTA: f64 = 1.0 / 60.0;
+
+    let sound_data = StaticSoundData::from_file(
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("assets/sounds/sound.ogg"),
+        StaticSoundSettings::default(),
+    )
+    .unwrap();
+    let sample_rate = sound_data.sample_rate as usize;
+    let samples = viseme::mono_samples(&sound_data);
+    // The first words, from 1.4 to 2.1 seconds.
+    let speech = &samples[sample_rate * 14 / 10..sample_rate * 21 / 10];
+
+    let stream = LipSyncStream::new(sample_rate as u32, 1, AnalysisSettings::default());
+    let writer = stream.writer();
+
+    let mut world = World::new();
+    world.insert_resource(Time::<()>::default());
+    let entity = world.spawn((stream, MouthShape::default())).id();
+
+    let step = |world: &mut World| {
+        world
+            .resource_mut::<Time>()
+            .advance_by(Duration::from_secs_f64(DELTA));
+        world.run_system_once(update_stream_mouth_shapes);
+        world.get::<MouthShape>(entity).unwrap().0
+    };
+
+    // Pushed a frame's worth at a time, like a live stream.
+    let mut opened = false;
+    for chunk in speech.chunks((sample_rate as f64 * DELTA) as usize) {
+        writer.push(chunk);
+        opened |= step(&mut world).iter().any(|weight| *weight > 0.1);
+    }
+    assert!(opened, "The mouth should open while speech arrives");
+
+    // Without new samples, the mouth closes once the window only holds old audio.
+    let mut mouth = [1.0; 5];
+    for _ in 0..60 {
+        mouth = step(&mut world);
+    }
+    assert!(mouth.iter().all(|weight| *weight < 0.01), "{:?}", mouth);
+}
@@ -3,23 +3,45 @@ use bevy::prelude::*;
 use bevy::render::camera::RenderTarget::Image;
 use bevy::render::texture::DefaultImageSampler;
 use bevy::window::{ExitCondition, WindowMode, WindowResolution};
+use bevy_demo::{glb, viseme, vrm_gltf};
 use bevy_inspector_egui::quick::WorldInspectorPlugin;
 use std::slice::Windows;
 
 mod animated_sprite;
+mod avatar_export;
 mod camera;
 mod debug_label;
+mod kira_audio;
+mod lip_sync;
+mod lip_sync_stream;
+mod morph_debug;
+mod morph_keys;
+mod morph_panel;
+mod morph_presets;
+mod morph_recorder;
+mod morph_rules;
 mod morph_targets;
 mod morph_viewer_plugin;
+mod phoneme;
 mod scene_viewer;
-mod vrm_gltf;
-
-pub use gltf::json as gltf_json;
+mod speech_controls;
+mod vrm_meta;
 
+use crate::avatar_export::AvatarExportPlugin;
 use crate::debug_label::DebugLabelPlugin;
+use crate::kira_audio::KiraAudioPlugin;
+use crate::lip_sync::LipSyncPlugin;
+use crate::lip_sync_stream::LipSyncStreamPlugin;
+use crate::morph_debug::MorphDebugPlugin;
+use crate::morph_panel::MorphPanelPlugin;
+use crate::morph_presets::MorphPresetsPlugin;
+use crate::morph_recorder::MorphRecorderPlugin;
+use crate::morph_rules::MorphRulesPlugin;
 use crate::morph_targets::VrmPlugin;
 use crate::morph_viewer_plugin::MorphViewerPlugin;
 use crate::scene_viewer::SceneViewerPlugin;
+use crate::speech_controls::SpeechControlsPlugin;
+use crate::vrm_meta::VrmMetaPlugin;
 
 fn main() {
     App::new()
@@ -42,6 +64,20 @@ fn main() {
         .add_plugins(SceneViewerPlugin)
         .add_plugins(DebugLabelPlugin)
         .add_plugins(AnimatedSpritePlugin)
-        .add_plugins((VrmPlugin, MorphViewerPlugin))
+        .add_plugins((
+            KiraAudioPlugin,
+            VrmPlugin,
+            LipSyncPlugin,
+            LipSyncStreamPlugin,
+            SpeechControlsPlugin,
+            VrmMetaPlugin,
+            MorphViewerPlugin,
+            MorphPanelPlugin,
+            MorphPresetsPlugin,
+            MorphRecorderPlugin,
+            MorphDebugPlugin,
+            MorphRulesPlugin,
+            AvatarExportPlugin,
+        ))
         .run();
 }
@@ -0,0 +1,287 @@
+//! Shows which vertices a morph target moves.
+//!
+//! The "Morph Debug" window picks a target. Its meshes can be coloured by the length of the
+//! position deltas, from blue for small to red for the largest, and the deltas can be drawn as
+//! lines from each vertex. Lines start at the bind pose, so they're off for skinned meshes in
+//! other poses.
+
+use crate::morph_viewer_plugin::WeightsControl;
+use bevy::prelude::*;
+use bevy::render::mesh::VertexAttributeValues;
+use bevy_inspector_egui::bevy_egui::{egui, EguiContexts};
+
+/// Floats per vertex in morph target images: position, normal and tangent deltas.
+pub const MORPH_COMPONENTS: usize = 9;
+
+/// Deltas shorter than this don't count as moved.
+const MIN_DELTA: f32 = 1e-6;
+
+#[derive(Resource, Clone, PartialEq)]
+struct MorphDebugView {
+    /// The entity with the [`MorphWeights`] and the index of the target.
+    selected: Option<(Entity, usize)>,
+    heatmap: bool,
+    vectors: bool,
+    /// Length of the drawn lines per unit of delta.
+    vector_scale: f32,
+}
+
+impl Default for MorphDebugView {
+    fn default() -> Self {
+        MorphDebugView {
+            selected: None,
+            heatmap: true,
+            vectors: false,
+            vector_scale: 1.0,
+        }
+    }
+}
+
+/// The handles a mesh had before it showed the heatmap.
+#[derive(Component)]
+struct HeatmapOriginal {
+    mesh: Handle<Mesh>,
+    material: Handle<StandardMaterial>,
+}
+
+/// Position deltas of the morph target `target`, read from a morph target image.
+/// Each layer of the image is a target, with [`MORPH_COMPONENTS`] floats per vertex.
+pub fn position_deltas(image: &Image, target: usize, vertex_count: usize) -> Option<Vec<Vec3>> {
+    let size = image.texture_descriptor.size;
+    let layer_len = (size.width * size.height) as usize * 4;
+    if vertex_count * MORPH_COMPONENTS * 4 > layer_len {
+        return None;
+    }
+
+    let layer = image
+        .data
+        .get(target * layer_len..(target + 1) * layer_len)?;
+
+    let deltas = layer
+        .chunks_exact(MORPH_COMPONENTS * 4)
+        .take(vertex_count)
+        .map(|vertex| {
+            let float = |i: usize| f32::from_le_bytes(vertex[i * 4..i * 4 + 4].try_into().unwrap());
+            Vec3::new(float(0), float(1), float(2))
+        })
+        .collect();
+
+    Some(deltas)
+}
+
+/// Blue to red by the length of each delta relative to the longest, grey for unmoved vertices.
+fn heatmap_colors(deltas: &[Vec3]) -> Vec<[f32; 4]> {
+    let longest = deltas.iter().map(|d| d.length()).fold(0.0, f32::max);
+
+    deltas
+        .iter()
+        .map(|delta| {
+            let length = delta.length();
+            if length < MIN_DELTA {
+                [0.2, 0.2, 0.2, 1.0]
+            } else {
+                let t = length / longest;
+                [t, 0.0, 1.0 - t, 1.0]
+            }
+        })
+        .collect()
+}
+
+/// Position deltas of the selected target for a mesh.
+fn mesh_deltas(mesh: &Mesh, images: &Assets<Image>, target: usize) -> Option<Vec<Vec3>> {
+    let image = images.get(mesh.morph_targets()?)?;
+    position_deltas(image, target, mesh.count_vertices())
+}
+
+fn show_debug_window(
+    mut contexts: EguiContexts,
+    mut view: ResMut<MorphDebugView>,
+    controls: Query<&WeightsControl>,
+) {
+    let mut new_view = view.clone();
+
+    let label = |selected: Option<(Entity, usize)>| {
+        let target = selected.and_then(|(entity, index)| {
+            controls
+                .iter()
+                .flat_map(|control| &control.weights)
+                .find(|target| target.entity == entity && target.index == index)
+        });
+
+        match target {
+            Some(target) => format!(
+                "{} of {}",
+                target.name().unwrap_or("unnamed"),
+                target.entity_name().unwrap_or("unnamed")
+            ),
+            None => "None".to_string(),
+        }
+    };
+
+    egui::Window::new("Morph Debug").show(contexts.ctx_mut(), |ui| {
+        egui::ComboBox::from_label("Target")
+            .selected_text(label(new_view.selected))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut new_view.selected, None, "None");
+                for target in controls.iter().flat_map(|control| &control.weights) {
+                    let value = Some((target.entity, target.index));
+                    ui.selectable_value(&mut new_view.selected, value, label(value));
+                }
+            });
+
+        ui.checkbox(&mut new_view.heatmap, "Heatmap");
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut new_view.vectors, "Vectors");
+            let slider = egui::Slider::new(&mut new_view.vector_scale, 0.1..=10.0);
+            ui.add(slider.logarithmic(true));
+        });
+    });
+
+    // Only touch the view on changes, as changes rebuild the heatmap.
+    view.set_if_neq(new_view);
+}
+
+/// Swaps the meshes below the selected entity for coloured copies with an unlit material.
+#[allow(clippy::too_many_arguments)]
+fn update_heatmap(
+    mut commands: Commands,
+    view: Res<MorphDebugView>,
+    mut material: Local<Option<Handle<StandardMaterial>>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    images: Res<Assets<Image>>,
+    mut mesh_query: Query<(
+        Entity,
+        Option<&HeatmapOriginal>,
+        &mut Handle<Mesh>,
+        &mut Handle<StandardMaterial>,
+    )>,
+    children_query: Query<&Children>,
+) {
+    if !view.is_changed() {
+        return;
+    }
+
+    for (entity, original, mut mesh, mut mesh_material) in &mut mesh_query {
+        let Some(original) = original else {
+            continue;
+        };
+
+        *mesh = original.mesh.clone();
+        *mesh_material = original.material.clone();
+        commands.entity(entity).remove::<HeatmapOriginal>();
+    }
+
+    let (true, Some((selected, target))) = (view.heatmap, view.selected) else {
+        return;
+    };
+
+    let material = material
+        .get_or_insert_with(|| {
+            materials.add(StandardMaterial {
+                base_color: Color::WHITE,
+                unlit: true,
+                ..default()
+            })
+        })
+        .clone();
+
+    for entity in children_query.iter_descendants(selected) {
+        let Ok((_, _, mut mesh, mut mesh_material)) = mesh_query.get_mut(entity) else {
+            continue;
+        };
+
+        let Some(mut colored) = meshes.get(mesh.id()).cloned() else {
+            continue;
+        };
+        let Some(deltas) = mesh_deltas(&colored, &images, target) else {
+            continue;
+        };
+
+        colored.insert_attribute(Mesh::ATTRIBUTE_COLOR, heatmap_colors(&deltas));
+
+        let heatmap = meshes.add(colored);
+        commands.entity(entity).insert(HeatmapOriginal {
+            mesh: std::mem::replace(&mut *mesh, heatmap),
+            material: std::mem::replace(&mut *mesh_material, material.clone()),
+        });
+    }
+}
+
+fn draw_vectors(
+    mut gizmos: Gizmos,
+    view: Res<MorphDebugView>,
+    meshes: Res<Assets<Mesh>>,
+    images: Res<Assets<Image>>,
+    mesh_query: Query<(&Handle<Mesh>, &GlobalTransform)>,
+    children_query: Query<&Children>,
+) {
+    let (true, Some((selected, target))) = (view.vectors, view.selected) else {
+        return;
+    };
+
+    for entity in children_query.iter_descendants(selected) {
+        let Ok((mesh, transform)) = mesh_query.get(entity) else {
+            continue;
+        };
+        let Some(mesh) = meshes.get(mesh) else {
+            continue;
+        };
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            continue;
+        };
+        let Some(deltas) = mesh_deltas(mesh, &images, target) else {
+            continue;
+        };
+
+        for (position, delta) in positions.iter().zip(deltas) {
+            if delta.length() < MIN_DELTA {
+                continue;
+            }
+
+            let start = Vec3::from(*position);
+            let end = start + delta * view.vector_scale;
+            gizmos.line(
+                transform.transform_point(start),
+                transform.transform_point(end),
+                Color::YELLOW,
+            );
+        }
+    }
+}
+
+pub struct MorphDebugPlugin;
+
+impl Plugin for MorphDebugPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MorphDebugView>().add_systems(
+            Update,
+            (show_debug_window, update_heatmap, draw_vectors).chain(),
+        );
+    }
+}
+
+#[test]
+fn test_position_deltas() {
+    use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+
+    // Two vertices and two targets, only the second moving the second vertex.
+    let mut floats = vec![0.0f32; 2 * 2 * MORPH_COMPONENTS];
+    floats[3 * MORPH_COMPONENTS..3 * MORPH_COMPONENTS + 3].copy_from_slice(&[0.0, 0.5, 0.0]);
+    let data = floats.iter().flat_map(|f| f.to_le_bytes()).collect();
+
+    let size = Extent3d {
+        width: 2 * MORPH_COMPONENTS as u32,
+        height: 1,
+        depth_or_array_layers: 2,
+    };
+    let image = Image::new(size, TextureDimension::D3, data, TextureFormat::R32Float);
+
+    let deltas = position_deltas(&image, 1, 2).unwrap();
+    assert_eq!(deltas, vec![Vec3::ZERO, Vec3::new(0.0, 0.5, 0.0)]);
+    assert_eq!(heatmap_colors(&deltas)[1], [1.0, 0.0, 0.0, 1.0]);
+    assert!(position_deltas(&image, 2, 2).is_none());
+    assert!(position_deltas(&image, 0, 3).is_none());
+}
@@ -0,0 +1,311 @@
+//! Keys controlling morph targets, loaded from `assets/morph_keys.json`.
+//!
+//! ```json
+//! {
+//!     "keys": ["R", "T", "Shift+R", "Ctrl+Alt+1"],
+//!     "next_page": "PageDown",
+//!     "previous_page": "PageUp"
+//! }
+//! ```
+//!
+//! Targets are split into pages of as many targets as there are keys.
+
+use bevy::prelude::*;
+use bevy_inspector_egui::bevy_egui::{EguiContexts, EguiSet};
+use serde::Deserialize;
+use std::cmp::max;
+use std::fmt;
+
+const KEY_MAP_PATH: &str = "assets/morph_keys.json";
+
+pub const ALL_MODIFIERS: &[KeyCode] = &[KeyCode::ShiftLeft, KeyCode::ControlLeft, KeyCode::AltLeft];
+
+/// Keys of the built-in map, each used without and with shift.
+const DEFAULT_KEYS: &str = "RTZIOPFGHJKYXCVBNM0123456789";
+
+pub struct KeyBinding {
+    pub name: String,
+    pub modifiers: Vec<KeyCode>,
+    pub key: KeyCode,
+}
+
+impl KeyBinding {
+    /// Parses e.g. `"R"`, `"Shift+R"` or `"Ctrl+Alt+F1"`.
+    pub fn parse(name: &str) -> Option<Self> {
+        let mut parts: Vec<&str> = name.split('+').map(str::trim).collect();
+        let key = parse_key(parts.pop()?)?;
+
+        let modifiers = parts
+            .into_iter()
+            .map(|modifier| match modifier.to_lowercase().as_str() {
+                "shift" => Some(KeyCode::ShiftLeft),
+                "ctrl" | "control" => Some(KeyCode::ControlLeft),
+                "alt" => Some(KeyCode::AltLeft),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(KeyBinding {
+            name: name.to_string(),
+            modifiers,
+            key,
+        })
+    }
+
+    /// Whether the key is pressed with exactly the modifiers of the binding.
+    pub fn active(&self, inputs: &Input<KeyCode>) -> bool {
+        let mut modifier = self.modifiers.iter();
+        let mut non_modifier = ALL_MODIFIERS.iter().filter(|m| !self.modifiers.contains(m));
+
+        let key = inputs.pressed(self.key);
+        let modifier = modifier.all(|m| inputs.pressed(*m));
+        let non_modifier = non_modifier.all(|m| !inputs.pressed(*m));
+        key && modifier && non_modifier
+    }
+
+    /// Whether the key was just pressed with alt added to the modifiers of the binding.
+    pub fn mode_switch_pressed(&self, inputs: &Input<KeyCode>) -> bool {
+        !self.modifiers.contains(&KeyCode::AltLeft)
+            && inputs.pressed(KeyCode::AltLeft)
+            && inputs.just_pressed(self.key)
+            && self.modifiers.iter().all(|m| inputs.pressed(*m))
+    }
+}
+
+impl fmt::Display for KeyBinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+fn parse_key(name: &str) -> Option<KeyCode> {
+    let upper = name.to_uppercase();
+
+    let letter = |c: char| -> Option<KeyCode> {
+        Some(match c {
+            'A' => KeyCode::A,
+            'B' => KeyCode::B,
+            'C' => KeyCode::C,
+            'D' => KeyCode::D,
+            'E' => KeyCode::E,
+            'F' => KeyCode::F,
+            'G' => KeyCode::G,
+            'H' => KeyCode::H,
+            'I' => KeyCode::I,
+            'J' => KeyCode::J,
+            'K' => KeyCode::K,
+            'L' => KeyCode::L,
+            'M' => KeyCode::M,
+            'N' => KeyCode::N,
+            'O' => KeyCode::O,
+            'P' => KeyCode::P,
+            'Q' => KeyCode::Q,
+            'R' => KeyCode::R,
+            'S' => KeyCode::S,
+            'T' => KeyCode::T,
+            'U' => KeyCode::U,
+            'V' => KeyCode::V,
+            'W' => KeyCode::W,
+            'X' => KeyCode::X,
+            'Y' => KeyCode::Y,
+            'Z' => KeyCode::Z,
+            '0' => KeyCode::Key0,
+            '1' => KeyCode::Key1,
+            '2' => KeyCode::Key2,
+            '3' => KeyCode::Key3,
+            '4' => KeyCode::Key4,
+            '5' => KeyCode::Key5,
+            '6' => KeyCode::Key6,
+            '7' => KeyCode::Key7,
+            '8' => KeyCode::Key8,
+            '9' => KeyCode::Key9,
+            _ => return None,
+        })
+    };
+
+    let mut chars = upper.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return letter(c);
+    }
+
+    Some(match upper.as_str() {
+        "F1" => KeyCode::F1,
+        "F2" => KeyCode::F2,
+        "F3" => KeyCode::F3,
+        "F4" => KeyCode::F4,
+        "F5" => KeyCode::F5,
+        "F6" => KeyCode::F6,
+        "F7" => KeyCode::F7,
+        "F8" => KeyCode::F8,
+        "F9" => KeyCode::F9,
+        "F10" => KeyCode::F10,
+        "F11" => KeyCode::F11,
+        "F12" => KeyCode::F12,
+        "PAGEUP" => KeyCode::PageUp,
+        "PAGEDOWN" => KeyCode::PageDown,
+        "HOME" => KeyCode::Home,
+        "END" => KeyCode::End,
+        "INSERT" => KeyCode::Insert,
+        "DELETE" => KeyCode::Delete,
+        "TAB" => KeyCode::Tab,
+        "MINUS" => KeyCode::Minus,
+        "EQUALS" => KeyCode::Equals,
+        "BRACKETLEFT" => KeyCode::BracketLeft,
+        "BRACKETRIGHT" => KeyCode::BracketRight,
+        "SEMICOLON" => KeyCode::Semicolon,
+        "APOSTROPHE" => KeyCode::Apostrophe,
+        "SLASH" => KeyCode::Slash,
+        "BACKSLASH" => KeyCode::Backslash,
+        _ => return None,
+    })
+}
+
+#[derive(Deserialize)]
+struct KeyMapFile {
+    keys: Vec<String>,
+    #[serde(default = "default_next_page")]
+    next_page: String,
+    #[serde(default = "default_previous_page")]
+    previous_page: String,
+}
+
+fn default_next_page() -> String {
+    "PageDown".to_string()
+}
+
+fn default_previous_page() -> String {
+    "PageUp".to_string()
+}
+
+#[derive(Resource)]
+pub struct MorphKeyMap {
+    pub bindings: Vec<KeyBinding>,
+    pub next_page: KeyCode,
+    pub previous_page: KeyCode,
+    /// The page of targets the bindings control.
+    pub page: usize,
+}
+
+impl Default for MorphKeyMap {
+    fn default() -> Self {
+        let keys = DEFAULT_KEYS.chars().map(|c| c.to_string());
+        let shifted = DEFAULT_KEYS.chars().map(|c| format!("Shift+{}", c));
+
+        MorphKeyMap {
+            bindings: keys
+                .chain(shifted)
+                .filter_map(|name| KeyBinding::parse(&name))
+                .collect(),
+            next_page: KeyCode::PageDown,
+            previous_page: KeyCode::PageUp,
+            page: 0,
+        }
+    }
+}
+
+impl MorphKeyMap {
+    fn from_json(bytes: &[u8]) -> Result<Self, String> {
+        let file: KeyMapFile = serde_json::from_slice(bytes).map_err(|error| error.to_string())?;
+
+        let parse = |name: &str| parse_key(name).ok_or(format!("Unknown key {:?}", name));
+
+        let bindings = file
+            .keys
+            .iter()
+            .map(|name| KeyBinding::parse(name).ok_or(format!("Invalid binding {:?}", name)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if bindings.is_empty() {
+            return Err("No keys".to_string());
+        }
+
+        Ok(MorphKeyMap {
+            bindings,
+            next_page: parse(&file.next_page)?,
+            previous_page: parse(&file.previous_page)?,
+            page: 0,
+        })
+    }
+
+    /// Number of pages for `count` targets.
+    pub fn page_count(&self, count: usize) -> usize {
+        max(1, (count + self.bindings.len() - 1) / self.bindings.len())
+    }
+
+    /// Range of the targets on the current page.
+    pub fn page_targets(&self, count: usize) -> std::ops::Range<usize> {
+        let start = (self.page * self.bindings.len()).min(count);
+        start..(start + self.bindings.len()).min(count)
+    }
+
+    /// Moves to the next or previous page when their keys are pressed.
+    pub fn change_page(&mut self, input: &Input<KeyCode>, count: usize) {
+        let page_count = self.page_count(count);
+
+        if input.just_pressed(self.next_page) {
+            self.page = (self.page + 1) % page_count;
+        } else if input.just_pressed(self.previous_page) {
+            self.page = (self.page + page_count - 1) % page_count;
+        } else {
+            // Targets may have been removed.
+            self.page = self.page.min(page_count - 1);
+        }
+    }
+
+    /// The binding of the target at `index`, if it's on the current page.
+    pub fn binding(&self, index: usize) -> Option<&KeyBinding> {
+        index
+            .checked_sub(self.page * self.bindings.len())
+            .and_then(|index| self.bindings.get(index))
+    }
+}
+
+fn load_key_map(mut commands: Commands) {
+    let key_map = match std::fs::read(KEY_MAP_PATH) {
+        Ok(bytes) => MorphKeyMap::from_json(&bytes).unwrap_or_else(|error| {
+            println!("Error loading {}: {}", KEY_MAP_PATH, error);
+            MorphKeyMap::default()
+        }),
+        Err(_) => MorphKeyMap::default(),
+    };
+
+    commands.insert_resource(key_map);
+}
+
+/// Whether egui wanted the keyboard last frame, e.g. for typing into a text field.
+#[derive(Resource, Default, PartialEq)]
+pub struct EguiKeyboardFocus(pub bool);
+
+fn track_egui_keyboard_focus(mut contexts: EguiContexts, mut focus: ResMut<EguiKeyboardFocus>) {
+    focus.set_if_neq(EguiKeyboardFocus(contexts.ctx_mut().wants_keyboard_input()));
+}
+
+/// Run condition for systems handling keys, false while egui has the keyboard.
+pub fn keyboard_free(focus: Option<Res<EguiKeyboardFocus>>) -> bool {
+    focus.map_or(true, |focus| !focus.0)
+}
+
+pub struct MorphKeysPlugin;
+
+impl Plugin for MorphKeysPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<EguiKeyboardFocus>()
+            .add_systems(PreStartup, load_key_map)
+            .add_systems(
+                PreUpdate,
+                track_egui_keyboard_focus.after(EguiSet::BeginFrame),
+            );
+    }
+}
+
+#[test]
+fn test_key_map() {
+    let key_map =
+        MorphKeyMap::from_json(br#"{ "keys": ["R", "Shift+R", "Ctrl+Alt+F1"] }"#).unwrap();
+    assert_eq!(key_map.bindings[1].modifiers, vec![KeyCode::ShiftLeft]);
+    assert_eq!(key_map.bindings[2].key, KeyCode::F1);
+    assert_eq!(key_map.page_count(7), 3);
+    assert_eq!(key_map.page_targets(7), 0..3);
+
+    assert!(MorphKeyMap::from_json(br#"{ "keys": ["Hyper+R"] }"#).is_err());
+}
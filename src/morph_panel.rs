@@ -0,0 +1,182 @@
+//! An egui window with a slider per morph target.
+//!
+//! Targets are listed per scene, grouped by their mesh entity and filtered by name. Solo and
+//! mute change what is applied, not the weights. VRM blend shape groups get a slider each,
+//! setting all their binds. Each target also picks how its applied weight follows the slider.
+
+use crate::morph_targets::VrmData;
+use crate::morph_viewer_plugin::{Interpolation, WeightsControl, DEFAULT_HALF_LIFE};
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use bevy_inspector_egui::bevy_egui::{egui, EguiContexts};
+
+#[derive(Default)]
+struct PanelState {
+    search: String,
+    /// Slider values of blend shape groups, by avatar and group name.
+    groups: HashMap<(Entity, String), f32>,
+}
+
+fn show_morph_panel(
+    mut contexts: EguiContexts,
+    mut state: Local<PanelState>,
+    mut scenes: Query<(Entity, Option<&Name>, Option<&VrmData>, &mut WeightsControl)>,
+    name_query: Query<&Name>,
+) {
+    if scenes.is_empty() {
+        return;
+    }
+    let state = state.as_mut();
+
+    egui::Window::new("Morph Targets").show(contexts.ctx_mut(), |ui| {
+        ui.horizontal(|ui| {
+            ui.label("Search");
+            ui.text_edit_singleline(&mut state.search);
+
+            if ui.button("Reset all").clicked() {
+                for (_, _, _, mut controls) in &mut scenes {
+                    controls.reset();
+                }
+                state.groups.clear();
+            }
+        });
+
+        let search = state.search.to_lowercase();
+        let found =
+            |name: Option<&str>| name.is_some_and(|name| name.to_lowercase().contains(&search));
+
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            for (root, root_name, vrm, mut controls) in &mut scenes {
+                match (vrm, root_name) {
+                    (Some(vrm), _) => ui.heading(&vrm.path),
+                    (None, Some(name)) => ui.heading(name.as_str()),
+                    (None, None) => ui.heading(format!("{:?}", root)),
+                };
+
+                if let Some(vrm) = vrm {
+                    egui::CollapsingHeader::new("Blend shapes")
+                        .id_source(root)
+                        .show(ui, |ui| {
+                            for group in &vrm.shape_key_groups {
+                                if !found(Some(group.name.as_str())) {
+                                    continue;
+                                }
+
+                                let value =
+                                    state.groups.entry((root, group.name.clone())).or_default();
+                                let slider = egui::Slider::new(value, 0.0..=1.0).text(&group.name);
+                                if !ui.add(slider).changed() {
+                                    continue;
+                                }
+
+                                for target in &mut controls.weights {
+                                    let Ok(name) = name_query.get(target.entity) else {
+                                        continue;
+                                    };
+
+                                    for (shape_key, weight) in &group.binds {
+                                        if shape_key.matches(name, target.index) {
+                                            target.weight = *value * weight;
+                                            target.active = false;
+                                        }
+                                    }
+                                }
+                            }
+                        });
+                }
+
+                // Mesh entities in the order their targets were detected.
+                let mut entities: Vec<Entity> = controls.weights.iter().map(|t| t.entity).collect();
+                entities.dedup();
+
+                for entity in entities {
+                    let mut targets: Vec<_> = controls
+                        .weights
+                        .iter_mut()
+                        .filter(|target| target.entity == entity)
+                        .filter(|target| {
+                            search.is_empty() || found(target.name()) || found(target.entity_name())
+                        })
+                        .collect();
+
+                    let Some(first) = targets.first() else {
+                        continue;
+                    };
+                    let heading = match first.entity_name() {
+                        Some(name) => name.to_string(),
+                        None => format!("{:?}", entity),
+                    };
+
+                    egui::CollapsingHeader::new(heading)
+                        .id_source(entity)
+                        .default_open(true)
+                        .show(ui, |ui| {
+                            for target in &mut targets {
+                                ui.horizontal(|ui| {
+                                    ui.toggle_value(&mut target.solo, "S");
+                                    ui.toggle_value(&mut target.muted, "M");
+                                    interpolation_ui(
+                                        ui,
+                                        (target.entity, target.index),
+                                        &mut target.interpolation,
+                                    );
+
+                                    let name = match target.name() {
+                                        Some(name) => name.to_string(),
+                                        None => format!("animation{}", target.index),
+                                    };
+                                    let slider =
+                                        egui::Slider::new(&mut target.weight, 0.0..=1.0).text(name);
+                                    if ui.add(slider).changed() {
+                                        target.active = false;
+                                    }
+                                });
+                            }
+                        });
+                }
+
+                ui.separator();
+            }
+        });
+    });
+}
+
+/// Picks the interpolation of a target, and its half-life.
+fn interpolation_ui(
+    ui: &mut egui::Ui,
+    id: impl std::hash::Hash,
+    interpolation: &mut Interpolation,
+) {
+    let half_life = match *interpolation {
+        Interpolation::Exponential { half_life } | Interpolation::Spring { half_life } => half_life,
+        Interpolation::Instant => DEFAULT_HALF_LIFE,
+    };
+
+    egui::ComboBox::from_id_source(id)
+        .selected_text(interpolation.to_string())
+        .show_ui(ui, |ui| {
+            ui.selectable_value(interpolation, Interpolation::Instant, "instant");
+            let exponential = Interpolation::Exponential { half_life };
+            ui.selectable_value(interpolation, exponential, "exponential");
+            let spring = Interpolation::Spring { half_life };
+            ui.selectable_value(interpolation, spring, "spring");
+        });
+
+    if let Interpolation::Exponential { half_life } | Interpolation::Spring { half_life } =
+        interpolation
+    {
+        let drag = egui::DragValue::new(half_life)
+            .speed(0.005)
+            .clamp_range(0.0..=2.0)
+            .suffix(" s");
+        ui.add(drag);
+    }
+}
+
+pub struct MorphPanelPlugin;
+
+impl Plugin for MorphPanelPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, show_morph_panel);
+    }
+}
@@ -0,0 +1,338 @@
+//! Named presets of morph target weights, saved as JSON files in `assets/morph_presets`.
+//!
+//! ```json
+//! { "weights": { "Face": { "Fcl_ALL_Joy": 1.0, "Fcl_EYE_Close": 0.3 } } }
+//! ```
+//!
+//! Weights are keyed by mesh entity name and morph target name, so presets survive changes to
+//! the order of meshes and targets. Unnamed targets aren't saved, and applying a preset leaves
+//! the targets it doesn't have alone.
+//!
+//! A preset belongs to no avatar in particular: it is captured from and applied to the avatar
+//! picked in the window only, as different avatars tend to have meshes of the same name.
+
+use crate::morph_targets::VrmData;
+use crate::morph_viewer_plugin::WeightsControl;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use bevy_inspector_egui::bevy_egui::{egui, EguiContexts};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+const PRESETS_DIR: &str = "assets/morph_presets";
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct MorphPreset {
+    /// Weights by mesh entity name, then morph target name.
+    pub weights: BTreeMap<String, BTreeMap<String, f32>>,
+}
+
+impl MorphPreset {
+    /// Captures the weights of the targets of one scene.
+    pub fn capture(controls: &WeightsControl) -> Self {
+        let mut preset = MorphPreset::default();
+
+        for target in &controls.weights {
+            let (Some(mesh), Some(name)) = (target.entity_name(), target.name()) else {
+                continue;
+            };
+
+            preset
+                .weights
+                .entry(mesh.to_string())
+                .or_default()
+                .insert(name.to_string(), target.weight);
+        }
+
+        preset
+    }
+
+    pub fn weight(&self, mesh: &str, target: &str) -> Option<f32> {
+        self.weights.get(mesh)?.get(target).copied()
+    }
+
+    /// The weights of the targets of `controls` that the preset has, by mesh entity and index.
+    fn target_weights<'a>(
+        &'a self,
+        controls: &'a WeightsControl,
+    ) -> impl Iterator<Item = (TargetKey, f32)> + 'a {
+        controls.weights.iter().filter_map(|target| {
+            let weight = self.weight(target.entity_name()?, target.name()?)?;
+            Some(((target.entity, target.index), weight))
+        })
+    }
+}
+
+/// A mesh entity and the index of one of its morph targets.
+type TargetKey = (Entity, usize);
+
+/// Moves the weights to a preset over time.
+/// Targets are keyed, as they can be added or removed during the fade.
+struct Fade {
+    /// Weights at the start.
+    from: HashMap<TargetKey, f32>,
+    to: HashMap<TargetKey, f32>,
+    /// In seconds.
+    elapsed: f32,
+    duration: f32,
+}
+
+impl Fade {
+    /// From 0 at the start to 1 at the end.
+    fn progress(&self) -> f32 {
+        if self.duration > 0.0 {
+            (self.elapsed / self.duration).min(1.0)
+        } else {
+            1.0
+        }
+    }
+
+    /// The current weight of a target, `None` for targets the fade leaves alone:
+    /// those missing from the preset or added since the fade started.
+    fn weight(&self, key: TargetKey) -> Option<f32> {
+        let (from, to) = (self.from.get(&key)?, self.to.get(&key)?);
+        Some(from + (to - from) * self.progress())
+    }
+}
+
+#[derive(Resource)]
+struct MorphPresets {
+    /// By name, sorted.
+    presets: Vec<(String, MorphPreset)>,
+    fade: Option<Fade>,
+    /// Cross-fade duration in seconds, 0 to apply at once.
+    fade_duration: f32,
+    /// Name to save the current weights as.
+    new_name: String,
+    /// Root of the scene presets are captured from and applied to.
+    scene: Option<Entity>,
+}
+
+impl Default for MorphPresets {
+    fn default() -> Self {
+        MorphPresets {
+            presets: vec![],
+            fade: None,
+            fade_duration: 0.5,
+            new_name: String::new(),
+            scene: None,
+        }
+    }
+}
+
+impl MorphPresets {
+    fn load(&mut self) {
+        self.presets.clear();
+
+        let Ok(entries) = std::fs::read_dir(PRESETS_DIR) else {
+            return;
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+                continue;
+            }
+            let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+
+            match load_preset(&path) {
+                Ok(preset) => self.presets.push((name.to_string(), preset)),
+                Err(error) => println!("Error loading preset {:?}: {}", path, error),
+            }
+        }
+
+        self.presets.sort_by(|a, b| a.0.cmp(&b.0));
+    }
+
+    fn save(&mut self, name: &str, preset: MorphPreset) {
+        if name.is_empty() || name.contains(['/', '\\', '.']) {
+            println!("Invalid preset name {:?}", name);
+            return;
+        }
+
+        let path = Path::new(PRESETS_DIR).join(format!("{}.json", name));
+        match save_preset(&path, &preset) {
+            Ok(()) => println!("Saved preset {:?}", path),
+            Err(error) => {
+                println!("Error saving preset {:?}: {}", path, error);
+                return;
+            }
+        }
+
+        match self
+            .presets
+            .binary_search_by(|(other, _)| other.as_str().cmp(name))
+        {
+            Ok(index) => self.presets[index].1 = preset,
+            Err(index) => self.presets.insert(index, (name.to_string(), preset)),
+        }
+    }
+}
+
+fn load_preset(path: &Path) -> Result<MorphPreset, String> {
+    let bytes = std::fs::read(path).map_err(|error| error.to_string())?;
+    serde_json::from_slice(&bytes).map_err(|error| error.to_string())
+}
+
+fn save_preset(path: &Path, preset: &MorphPreset) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(preset).map_err(|error| error.to_string())?;
+    std::fs::create_dir_all(PRESETS_DIR).map_err(|error| error.to_string())?;
+    std::fs::write(path, json).map_err(|error| error.to_string())
+}
+
+fn load_presets(mut presets: ResMut<MorphPresets>) {
+    presets.load();
+}
+
+fn show_presets(
+    mut contexts: EguiContexts,
+    mut presets: ResMut<MorphPresets>,
+    scenes: Query<(Entity, Option<&Name>, Option<&VrmData>, &WeightsControl)>,
+) {
+    let mut roots: Vec<Entity> = scenes.iter().map(|(root, ..)| root).collect();
+    roots.sort();
+    let Some(&first) = roots.first() else {
+        return;
+    };
+    let presets = presets.as_mut();
+
+    // Fall back to the first scene when none is picked yet or the picked one is gone.
+    let scene = presets
+        .scene
+        .filter(|scene| roots.contains(scene))
+        .unwrap_or(first);
+    presets.scene = Some(scene);
+
+    let label = |root: Entity| match scenes.get(root) {
+        Ok((_, _, Some(vrm), _)) => vrm.path.clone(),
+        Ok((_, Some(name), None, _)) => name.as_str().to_string(),
+        _ => format!("{:?}", root),
+    };
+    let Ok((_, _, _, controls)) = scenes.get(scene) else {
+        return;
+    };
+
+    egui::Window::new("Morph Presets").show(contexts.ctx_mut(), |ui| {
+        egui::ComboBox::from_label("Avatar")
+            .selected_text(label(scene))
+            .show_ui(ui, |ui| {
+                for &root in &roots {
+                    ui.selectable_value(&mut presets.scene, Some(root), label(root));
+                }
+            });
+
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut presets.new_name);
+
+            if ui.button("Save").clicked() {
+                let name = presets.new_name.trim().to_string();
+                presets.save(&name, MorphPreset::capture(controls));
+            }
+
+            if ui.button("Reload").clicked() {
+                presets.load();
+            }
+        });
+
+        ui.add(
+            egui::Slider::new(&mut presets.fade_duration, 0.0..=5.0)
+                .text("Cross-fade")
+                .suffix(" s"),
+        );
+
+        ui.separator();
+
+        let mut fade_to = None;
+        for (name, preset) in &presets.presets {
+            if ui.button(name).clicked() {
+                fade_to = Some(preset.target_weights(controls).collect());
+            }
+        }
+
+        if let Some(to) = fade_to {
+            presets.fade = Some(Fade {
+                from: controls
+                    .weights
+                    .iter()
+                    .map(|target| ((target.entity, target.index), target.weight))
+                    .collect(),
+                to,
+                elapsed: 0.0,
+                duration: presets.fade_duration,
+            });
+        }
+    });
+}
+
+fn fade_presets(
+    time: Res<Time>,
+    mut presets: ResMut<MorphPresets>,
+    mut controls: Query<&mut WeightsControl>,
+) {
+    let Some(fade) = presets.fade.as_mut() else {
+        return;
+    };
+
+    fade.elapsed += time.delta_seconds();
+
+    for mut control in &mut controls {
+        for target in &mut control.weights {
+            if let Some(weight) = fade.weight((target.entity, target.index)) {
+                target.weight = weight;
+                target.active = false;
+            }
+        }
+    }
+
+    if fade.progress() >= 1.0 {
+        presets.fade = None;
+    }
+}
+
+pub struct MorphPresetsPlugin;
+
+impl Plugin for MorphPresetsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MorphPresets>()
+            .add_systems(Startup, load_presets)
+            .add_systems(Update, (show_presets, fade_presets).chain());
+    }
+}
+
+#[test]
+fn test_preset_json() {
+    let preset: MorphPreset =
+        serde_json::from_str(r#"{ "weights": { "Face": { "Joy": 1.0, "Blink": 0.25 } } }"#)
+            .unwrap();
+    assert_eq!(preset.weight("Face", "Blink"), Some(0.25));
+    assert_eq!(preset.weight("Body", "Joy"), None);
+
+    let json = serde_json::to_string(&preset).unwrap();
+    assert_eq!(serde_json::from_str::<MorphPreset>(&json).unwrap(), preset);
+}
+
+#[test]
+fn test_fade() {
+    let (face, body) = (Entity::from_raw(1), Entity::from_raw(2));
+
+    let mut fade = Fade {
+        from: HashMap::from_iter([((face, 0), 0.0), ((face, 1), 1.0), ((body, 0), 0.5)]),
+        // The preset has no weight for the body.
+        to: HashMap::from_iter([((face, 0), 1.0), ((face, 1), 0.0), ((face, 2), 1.0)]),
+        elapsed: 0.25,
+        duration: 1.0,
+    };
+
+    assert_eq!(fade.weight((face, 0)), Some(0.25));
+    assert_eq!(fade.weight((face, 1)), Some(0.75));
+    assert_eq!(fade.weight((body, 0)), None);
+    // Added after the fade started.
+    assert_eq!(fade.weight((face, 2)), None);
+
+    fade.elapsed = 2.0;
+    assert_eq!(fade.progress(), 1.0);
+    assert_eq!(fade.weight((face, 0)), Some(1.0));
+}
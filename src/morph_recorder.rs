@@ -0,0 +1,485 @@
+//! Records morph weights over time, plays them back and exports them as glTF animations.
+//!
+//! Each scene root gets a [`MorphRecording`], with [`MorphTrack`]s per named mesh entity of both
+//! the applied [`MorphWeights`] and the [`WeightsControl`] targets. Exports use the applied
+//! weights, smoothed like they were shown. Playback drives the targets instead, so the viewer
+//! smooths them once, like the performance was. Keyframes that linear interpolation reproduces
+//! within a tolerance can be dropped when recording stops.
+//!
+//! Exports copy the avatar's file with an added animation, as `exports/<name>_morphs.<extension>`.
+
+use crate::avatar_export::{export_path, node_names};
+use crate::glb::{push, GlbFile};
+use crate::morph_targets::VrmData;
+use crate::morph_viewer_plugin::WeightsControl;
+use crate::vrm_gltf::VrmError;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use bevy_inspector_egui::bevy_egui::{egui, EguiContexts};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Morph weights of one mesh entity over time.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MorphTrack {
+    /// In seconds from the start of the recording, increasing.
+    pub times: Vec<f32>,
+    /// The weights at each time.
+    pub frames: Vec<Vec<f32>>,
+}
+
+impl MorphTrack {
+    pub fn push(&mut self, time: f32, weights: &[f32]) {
+        if self.times.last().is_some_and(|last| time <= *last) {
+            return;
+        }
+
+        self.times.push(time);
+        self.frames.push(weights.to_vec());
+    }
+
+    /// Drops keyframes whose weights are within `tolerance` of the line between their neighbours.
+    pub fn reduce(&mut self, tolerance: f32) {
+        let len = self.times.len();
+        if len < 3 {
+            return;
+        }
+
+        let mut kept = vec![0];
+        for next in 2..len {
+            let start = *kept.last().unwrap();
+            let (start_time, next_time) = (self.times[start], self.times[next]);
+
+            // Whether all keys between `start` and `next` are on the line between them.
+            let linear = (start + 1..next).all(|i| {
+                let t = (self.times[i] - start_time) / (next_time - start_time);
+                let from = &self.frames[start];
+                let to = &self.frames[next];
+                self.frames[i]
+                    .iter()
+                    .zip(from.iter().zip(to))
+                    .all(|(weight, (a, b))| (a + (b - a) * t - weight).abs() <= tolerance)
+            });
+
+            if !linear {
+                kept.push(next - 1);
+            }
+        }
+        kept.push(len - 1);
+
+        self.times = kept.iter().map(|&i| self.times[i]).collect();
+        self.frames = kept.iter().map(|&i| self.frames[i].clone()).collect();
+    }
+
+    /// Weights at `time`, interpolated linearly and held before the first and after the last key.
+    pub fn sample(&self, time: f32) -> Option<Vec<f32>> {
+        let next = self.times.partition_point(|t| *t <= time);
+
+        if next == 0 {
+            return self.frames.first().cloned();
+        }
+        if next == self.times.len() {
+            return self.frames.last().cloned();
+        }
+
+        let t = (time - self.times[next - 1]) / (self.times[next] - self.times[next - 1]);
+        let from = &self.frames[next - 1];
+        let to = &self.frames[next];
+        Some(from.iter().zip(to).map(|(a, b)| a + (b - a) * t).collect())
+    }
+}
+
+/// Tracks by the name of their mesh entity, which is the glTF node name.
+#[derive(Clone, Debug, Default)]
+pub struct MorphRecording {
+    /// The applied [`MorphWeights`], for exports.
+    pub tracks: BTreeMap<String, MorphTrack>,
+    /// The [`WeightsControl`] target weights, for playback.
+    pub targets: BTreeMap<String, MorphTrack>,
+}
+
+impl MorphRecording {
+    /// In seconds.
+    pub fn duration(&self) -> f32 {
+        self.tracks
+            .values()
+            .chain(self.targets.values())
+            .filter_map(|track| track.times.last())
+            .fold(0.0, |a, b| a.max(*b))
+    }
+
+    pub fn key_count(&self) -> usize {
+        self.tracks.values().map(|track| track.times.len()).sum()
+    }
+}
+
+/// Returns the bytes of `bytes` (a `.glb`/`.vrm` file) with `recording` added as an animation.
+/// Tracks of nodes the file doesn't have, or without a mesh, are left out. Fails if that leaves
+/// no track.
+pub fn export_recording(
+    bytes: &[u8],
+    recording: &MorphRecording,
+    name: &str,
+) -> Result<Vec<u8>, VrmError> {
+    let mut glb = GlbFile::from_slice(bytes)?;
+
+    let mut channels = vec![];
+    let mut samplers = vec![];
+
+    for (node, node_name) in node_names(&glb.json).iter().enumerate() {
+        let Some(track) = recording.tracks.get(node_name) else {
+            continue;
+        };
+        if track.times.is_empty() || glb.json["nodes"][node]["mesh"].is_null() {
+            continue;
+        }
+
+        let input = glb.push_accessor(&track.times, "SCALAR", 1);
+        let output = glb.push_accessor(&track.frames.concat(), "SCALAR", 1);
+
+        channels.push(json!({
+            "sampler": samplers.len(),
+            "target": { "node": node, "path": "weights" },
+        }));
+        samplers.push(json!({
+            "input": input,
+            "output": output,
+            "interpolation": "LINEAR",
+        }));
+    }
+
+    if channels.is_empty() {
+        return Err(VrmError::EmptyAnimation);
+    }
+
+    push(
+        &mut glb.json,
+        "animations",
+        json!({
+            "name": name,
+            "channels": channels,
+            "samplers": samplers,
+        }),
+    );
+
+    glb.to_vec()
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum RecorderState {
+    Idle,
+    /// Since the given elapsed time, in seconds.
+    Recording(f32),
+    Playing(f32),
+}
+
+#[derive(Resource)]
+struct MorphRecorder {
+    state: RecorderState,
+    /// By scene root.
+    recordings: HashMap<Entity, MorphRecording>,
+    /// Whether to reduce keyframes when recording stops.
+    reduce: bool,
+    tolerance: f32,
+}
+
+impl Default for MorphRecorder {
+    fn default() -> Self {
+        MorphRecorder {
+            state: RecorderState::Idle,
+            recordings: HashMap::new(),
+            reduce: true,
+            tolerance: 0.01,
+        }
+    }
+}
+
+impl MorphRecorder {
+    /// In seconds.
+    fn duration(&self) -> f32 {
+        self.recordings
+            .values()
+            .map(MorphRecording::duration)
+            .fold(0.0, f32::max)
+    }
+
+    fn key_count(&self) -> usize {
+        self.recordings
+            .values()
+            .map(MorphRecording::key_count)
+            .sum()
+    }
+
+    fn stop(&mut self) {
+        if let RecorderState::Recording(_) = self.state {
+            if self.reduce {
+                let tracks = self.recordings.values_mut().flat_map(|recording| {
+                    recording
+                        .tracks
+                        .values_mut()
+                        .chain(recording.targets.values_mut())
+                });
+                for track in tracks {
+                    track.reduce(self.tolerance);
+                }
+            }
+        }
+
+        self.state = RecorderState::Idle;
+    }
+}
+
+/// The target weights of each named mesh entity of a scene.
+fn target_frames(control: &WeightsControl) -> BTreeMap<&str, Vec<f32>> {
+    let mut frames: BTreeMap<&str, Vec<f32>> = BTreeMap::new();
+
+    for target in &control.weights {
+        let Some(name) = target.entity_name() else {
+            continue;
+        };
+
+        let frame = frames.entry(name).or_default();
+        if frame.len() <= target.index {
+            frame.resize(target.index + 1, 0.0);
+        }
+        frame[target.index] = target.weight;
+    }
+
+    frames
+}
+
+fn record_morphs(
+    time: Res<Time>,
+    mut recorder: ResMut<MorphRecorder>,
+    controls: Query<(Entity, &WeightsControl)>,
+    morphs: Query<(&Name, &MorphWeights)>,
+) {
+    let RecorderState::Recording(start) = recorder.state else {
+        return;
+    };
+    let elapsed = time.elapsed_seconds() - start;
+
+    for (root, control) in &controls {
+        let recording = recorder.recordings.entry(root).or_default();
+
+        for (name, frame) in target_frames(control) {
+            recording
+                .targets
+                .entry(name.to_string())
+                .or_default()
+                .push(elapsed, &frame);
+        }
+
+        let mut entities: Vec<Entity> = control.weights.iter().map(|t| t.entity).collect();
+        entities.dedup();
+
+        for (name, weights) in morphs.iter_many(entities) {
+            recording
+                .tracks
+                .entry(name.to_string())
+                .or_default()
+                .push(elapsed, weights.weights());
+        }
+    }
+}
+
+fn play_morphs(
+    time: Res<Time>,
+    mut recorder: ResMut<MorphRecorder>,
+    mut controls: Query<(Entity, &mut WeightsControl)>,
+) {
+    let RecorderState::Playing(start) = recorder.state else {
+        return;
+    };
+
+    let elapsed = time.elapsed_seconds() - start;
+
+    for (root, mut control) in &mut controls {
+        let Some(recording) = recorder.recordings.get(&root) else {
+            continue;
+        };
+
+        for target in &mut control.weights {
+            let Some(track) = target
+                .entity_name()
+                .and_then(|name| recording.targets.get(name))
+            else {
+                continue;
+            };
+
+            if let Some(&weight) = track
+                .sample(elapsed)
+                .as_ref()
+                .and_then(|w| w.get(target.index))
+            {
+                target.weight = weight;
+                target.active = false;
+            }
+        }
+    }
+
+    if elapsed > recorder.duration() {
+        recorder.stop();
+    }
+}
+
+/// Exports the recording of each avatar into a copy of its file.
+fn export_to_avatars(
+    recordings: &HashMap<Entity, MorphRecording>,
+    vrm_query: &Query<(Entity, &VrmData)>,
+) {
+    for (root, vrm) in vrm_query {
+        let Some(recording) = recordings.get(&root) else {
+            continue;
+        };
+
+        let source = Path::new("assets").join(&vrm.path);
+        let destination = export_path(&source, "morphs");
+
+        let result = std::fs::read(&source)
+            .map_err(VrmError::Io)
+            .and_then(|bytes| export_recording(&bytes, recording, "Morphs"))
+            .and_then(|bytes| {
+                std::fs::create_dir_all(destination.parent().unwrap_or(Path::new(".")))
+                    .and_then(|_| std::fs::write(&destination, bytes))
+                    .map_err(VrmError::Io)
+            });
+
+        match result {
+            Ok(_) => println!("Exported recording to {:?}", destination),
+            Err(error) => println!("Error exporting recording to {:?}: {}", destination, error),
+        }
+    }
+}
+
+fn show_recorder(
+    mut contexts: EguiContexts,
+    time: Res<Time>,
+    mut recorder: ResMut<MorphRecorder>,
+    vrm_query: Query<(Entity, &VrmData)>,
+) {
+    let recorder = recorder.as_mut();
+    let now = time.elapsed_seconds();
+
+    egui::Window::new("Morph Recorder").show(contexts.ctx_mut(), |ui| {
+        ui.horizontal(|ui| match recorder.state {
+            RecorderState::Idle => {
+                if ui.button("Record").clicked() {
+                    recorder.recordings.clear();
+                    recorder.state = RecorderState::Recording(now);
+                }
+                let has_recording = recorder.key_count() > 0;
+                if ui
+                    .add_enabled(has_recording, egui::Button::new("Play"))
+                    .clicked()
+                {
+                    recorder.state = RecorderState::Playing(now);
+                }
+                if ui
+                    .add_enabled(has_recording, egui::Button::new("Export"))
+                    .clicked()
+                {
+                    export_to_avatars(&recorder.recordings, &vrm_query);
+                }
+            }
+            RecorderState::Recording(start) | RecorderState::Playing(start) => {
+                if ui.button("Stop").clicked() {
+                    recorder.stop();
+                }
+                ui.label(format!("{:.1} s", now - start));
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut recorder.reduce, "Reduce keyframes");
+            ui.add(
+                egui::Slider::new(&mut recorder.tolerance, 0.001..=0.1)
+                    .logarithmic(true)
+                    .text("Tolerance"),
+            );
+        });
+
+        ui.label(format!(
+            "{} keys, {:.1} s",
+            recorder.key_count(),
+            recorder.duration()
+        ));
+    });
+}
+
+pub struct MorphRecorderPlugin;
+
+impl Plugin for MorphRecorderPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MorphRecorder>()
+            .add_systems(Update, (show_recorder, record_morphs, play_morphs).chain());
+    }
+}
+
+#[test]
+fn test_track_reduce_and_sample() {
+    let mut track = MorphTrack::default();
+    for frame in 0..=10 {
+        let time = frame as f32 * 0.1;
+        // A ramp up to 0.5 s, then a hold.
+        track.push(time, &[time.min(0.5), 1.0]);
+    }
+
+    track.reduce(0.001);
+    assert_eq!(track.times, vec![0.0, 0.5, 1.0]);
+    assert_eq!(track.sample(0.25), Some(vec![0.25, 1.0]));
+    assert_eq!(track.sample(2.0), Some(vec![0.5, 1.0]));
+}
+
+#[test]
+fn test_export_recording() {
+    let mut glb = GlbFile {
+        json: json!({
+            "asset": { "version": "2.0" },
+            "meshes": [{ "primitives": [] }],
+            "nodes": [{ "name": "Armature" }, { "name": "Face", "mesh": 0 }],
+        }),
+        bin: vec![],
+    };
+
+    let mut track = MorphTrack::default();
+    track.push(0.0, &[0.0, 1.0]);
+    track.push(0.5, &[1.0, 0.5]);
+
+    let mut recording = MorphRecording::default();
+    recording.tracks.insert("Face".to_string(), track.clone());
+    // Without a mesh, or not in the file.
+    recording
+        .tracks
+        .insert("Armature".to_string(), track.clone());
+    recording.tracks.insert("Body".to_string(), track);
+
+    let source = glb.to_vec().unwrap();
+    let bytes = export_recording(&source, &recording, "Morphs").unwrap();
+    let glb = GlbFile::from_slice(&bytes).unwrap();
+
+    let animation = &glb.json["animations"][0];
+    assert_eq!(animation["name"], "Morphs");
+    assert_eq!(animation["channels"].as_array().unwrap().len(), 1);
+    assert_eq!(
+        animation["channels"][0]["target"],
+        json!({ "node": 1, "path": "weights" })
+    );
+
+    let sampler = &animation["samplers"][0];
+    assert_eq!(sampler["interpolation"], "LINEAR");
+    let floats = |accessor: &serde_json::Value| {
+        let layout = glb.float_accessor(accessor.as_u64().unwrap() as usize);
+        glb.read_floats(layout.unwrap())
+    };
+    assert_eq!(floats(&sampler["input"]), vec![0.0, 0.5]);
+    assert_eq!(floats(&sampler["output"]), vec![0.0, 1.0, 1.0, 0.5]);
+
+    // No track left to export.
+    recording.tracks.remove("Face");
+    assert!(matches!(
+        export_recording(&source, &recording, "Morphs"),
+        Err(VrmError::EmptyAnimation)
+    ));
+}
@@ -0,0 +1,338 @@
+//! Left/right splits and corrective shapes, configured in `assets/morph_rules.json`.
+//!
+//! ```json
+//! {
+//!     "splits": [{
+//!         "mesh": "Face",
+//!         "target": "Fcl_BRW_Angry",
+//!         "positive_x": "Fcl_BRW_Angry_R",
+//!         "negative_x": "Fcl_BRW_Angry_L",
+//!         "blend_width": 0.01
+//!     }],
+//!     "correctives": [{ "target": "Fcl_MTH_A_Joy", "drivers": ["Fcl_MTH_A", "Fcl_ALL_Joy"] }]
+//! }
+//! ```
+//!
+//! A split adds two targets to a mesh, with the deltas of the vertices at positive and negative X,
+//! blended across `blend_width` around X = 0. Positive X is the character's left for models facing
+//! +Z (VRM 1.0), and its right for models facing -Z (VRM 0.x).
+//!
+//! A corrective target is driven by the product of the weights of its drivers, so it only fires
+//! when all of them are active. `mesh` is optional and limits a rule to the entity of that name.
+
+use crate::morph_debug::MORPH_COMPONENTS;
+use crate::morph_viewer_plugin::update_morphs;
+use bevy::prelude::*;
+use bevy::render::mesh::morph::{MeshMorphWeights, MAX_MORPH_WEIGHTS};
+use bevy::render::mesh::VertexAttributeValues;
+use bevy::render::render_resource::{TextureDimension, TextureFormat};
+use serde::Deserialize;
+
+const RULES_PATH: &str = "assets/morph_rules.json";
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct SplitRule {
+    pub mesh: Option<String>,
+    pub target: String,
+    pub positive_x: String,
+    pub negative_x: String,
+    /// In model units, 0 for a hard cut.
+    #[serde(default)]
+    pub blend_width: f32,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct CorrectiveRule {
+    pub mesh: Option<String>,
+    pub target: String,
+    pub drivers: Vec<String>,
+}
+
+#[derive(Resource, Deserialize, Clone, Debug, Default)]
+pub struct MorphRules {
+    #[serde(default)]
+    pub splits: Vec<SplitRule>,
+    #[serde(default)]
+    pub correctives: Vec<CorrectiveRule>,
+}
+
+/// Marks mesh entities whose splits were added.
+#[derive(Component)]
+struct SplitsApplied;
+
+fn applies(mesh: &Option<String>, name: &str) -> bool {
+    mesh.as_deref().map_or(true, |mesh| mesh == name)
+}
+
+/// How much of the delta of a vertex at `x` goes to the positive X half.
+fn positive_factor(x: f32, blend_width: f32) -> f32 {
+    if blend_width > 0.0 {
+        (x / blend_width + 0.5).clamp(0.0, 1.0)
+    } else if x > 0.0 {
+        1.0
+    } else if x < 0.0 {
+        0.0
+    } else {
+        0.5
+    }
+}
+
+/// Scales the deltas of each vertex of a morph target image layer.
+fn scaled_layer(layer: &[u8], factors: impl Iterator<Item = f32>) -> Vec<u8> {
+    let mut scaled = layer.to_vec();
+
+    for (vertex, factor) in scaled.chunks_exact_mut(MORPH_COMPONENTS * 4).zip(factors) {
+        for float in vertex.chunks_exact_mut(4) {
+            let value = f32::from_le_bytes((&*float).try_into().unwrap()) * factor;
+            float.copy_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    scaled
+}
+
+/// A copy of a morph target image with positive and negative X layers appended
+/// for each `(layer, blend_width)`.
+fn split_image(image: &Image, splits: &[(usize, f32)], positions: &[[f32; 3]]) -> Option<Image> {
+    let mut size = image.texture_descriptor.size;
+    let layer_len = (size.width * size.height) as usize * 4;
+    let mut data = image.data.clone();
+
+    for &(layer, blend_width) in splits {
+        let layer = image.data.get(layer * layer_len..(layer + 1) * layer_len)?;
+
+        let positive = positions.iter().map(|p| positive_factor(p[0], blend_width));
+        data.extend(scaled_layer(layer, positive));
+        let negative = positions
+            .iter()
+            .map(|p| 1.0 - positive_factor(p[0], blend_width));
+        data.extend(scaled_layer(layer, negative));
+    }
+
+    size.depth_or_array_layers += 2 * splits.len() as u32;
+    Some(Image::new(
+        size,
+        TextureDimension::D3,
+        data,
+        TextureFormat::R32Float,
+    ))
+}
+
+fn split_mesh(mesh: &Mesh, images: &Assets<Image>, splits: &[(usize, f32)]) -> Option<Image> {
+    let image = images.get(mesh.morph_targets()?)?;
+    let Some(VertexAttributeValues::Float32x3(positions)) =
+        mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+    else {
+        return None;
+    };
+
+    split_image(image, splits, positions)
+}
+
+/// Product of the driver weights.
+fn corrective_weight(drivers: impl IntoIterator<Item = f32>) -> f32 {
+    drivers.into_iter().map(|w| w.clamp(0.0, 1.0)).product()
+}
+
+fn load_rules(mut commands: Commands) {
+    let rules = match std::fs::read(RULES_PATH) {
+        Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|error| {
+            println!("Error loading {}: {}", RULES_PATH, error);
+            MorphRules::default()
+        }),
+        Err(_) => MorphRules::default(),
+    };
+
+    commands.insert_resource(rules);
+}
+
+/// Replaces the meshes of entities with split rules by copies with the split targets.
+fn apply_splits(
+    mut commands: Commands,
+    rules: Res<MorphRules>,
+    nodes: Query<(Entity, &Name, &MorphWeights, &Children), Without<SplitsApplied>>,
+    primitives: Query<&Handle<Mesh>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    for (node, name, weights, children) in &nodes {
+        // Wait for the meshes.
+        let Some(first_mesh) = weights.first_mesh().and_then(|h| meshes.get(h)) else {
+            continue;
+        };
+        if primitives
+            .iter_many(children.iter())
+            .any(|handle| meshes.get(handle).is_none())
+        {
+            continue;
+        }
+
+        commands.entity(node).insert(SplitsApplied);
+
+        let names = first_mesh.morph_target_names().unwrap_or_default().to_vec();
+
+        let splits: Vec<&SplitRule> = rules
+            .splits
+            .iter()
+            .filter(|rule| applies(&rule.mesh, name))
+            .filter(|rule| names.contains(&rule.target) && !names.contains(&rule.positive_x))
+            .collect();
+        if splits.is_empty() {
+            continue;
+        }
+
+        let mut new_names = names.clone();
+        let mut layers = vec![];
+        for rule in &splits {
+            let layer = names.iter().position(|n| n == &rule.target).unwrap();
+            layers.push((layer, rule.blend_width));
+            new_names.push(rule.positive_x.clone());
+            new_names.push(rule.negative_x.clone());
+        }
+
+        if new_names.len() > MAX_MORPH_WEIGHTS {
+            println!(
+                "Can't split morph targets of {}: more than {} targets",
+                name, MAX_MORPH_WEIGHTS
+            );
+            continue;
+        }
+
+        let mut new_first_mesh = None;
+
+        for &child in children.iter() {
+            let Ok(handle) = primitives.get(child) else {
+                continue;
+            };
+            let Some(mesh) = meshes.get(handle) else {
+                continue;
+            };
+
+            let Some(image) = split_mesh(mesh, &images, &layers) else {
+                println!("Can't split morph targets of {}", name);
+                continue;
+            };
+
+            let mut mesh = mesh.clone();
+            mesh.set_morph_targets(images.add(image));
+            mesh.set_morph_target_names(new_names.clone());
+
+            let handle = meshes.add(mesh);
+            new_first_mesh.get_or_insert(handle.clone());
+
+            match MeshMorphWeights::new(vec![0.0; new_names.len()]) {
+                Ok(mesh_weights) => {
+                    commands.entity(child).insert((handle, mesh_weights));
+                }
+                Err(error) => println!("Error splitting morph targets of {}: {:?}", name, error),
+            }
+        }
+
+        let mut new_weights = weights.weights().to_vec();
+        new_weights.resize(new_names.len(), 0.0);
+
+        match MorphWeights::new(new_weights, new_first_mesh) {
+            Ok(weights) => {
+                // Removed and added again, so the viewer picks up the new targets.
+                commands
+                    .entity(node)
+                    .remove::<MorphWeights>()
+                    .insert(weights);
+                println!("Split {} morph targets of {}", splits.len(), name);
+            }
+            Err(error) => println!("Error splitting morph targets of {}: {:?}", name, error),
+        }
+    }
+}
+
+/// Overrides the weights of corrective targets after the viewer updated them.
+fn apply_correctives(
+    rules: Res<MorphRules>,
+    mut nodes: Query<(&Name, &mut MorphWeights)>,
+    meshes: Res<Assets<Mesh>>,
+) {
+    if rules.correctives.is_empty() {
+        return;
+    }
+
+    for (name, mut weights) in &mut nodes {
+        let Some(names) = weights
+            .first_mesh()
+            .and_then(|h| meshes.get(h))
+            .and_then(|m| m.morph_target_names())
+        else {
+            continue;
+        };
+        let index = |target: &str| names.iter().position(|n| n == target);
+
+        for rule in &rules.correctives {
+            if !applies(&rule.mesh, name) || rule.drivers.is_empty() {
+                continue;
+            }
+            let Some(target) = index(&rule.target) else {
+                continue;
+            };
+            let Some(drivers) = rule
+                .drivers
+                .iter()
+                .map(|driver| index(driver))
+                .collect::<Option<Vec<_>>>()
+            else {
+                continue;
+            };
+
+            let weight = corrective_weight(drivers.iter().map(|&i| weights.weights()[i]));
+            if weights.weights()[target] != weight {
+                weights.weights_mut()[target] = weight;
+            }
+        }
+    }
+}
+
+pub struct MorphRulesPlugin;
+
+impl Plugin for MorphRulesPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(PreStartup, load_rules).add_systems(
+            Update,
+            (apply_splits, apply_correctives.after(update_morphs)),
+        );
+    }
+}
+
+#[test]
+fn test_split_and_correctives() {
+    use bevy::render::render_resource::Extent3d;
+
+    // One target moving two vertices, at X = -1 and 1, up by 1.
+    let mut floats = vec![0.0f32; 2 * MORPH_COMPONENTS];
+    floats[1] = 1.0;
+    floats[MORPH_COMPONENTS + 1] = 1.0;
+    let data = floats.iter().flat_map(|f| f.to_le_bytes()).collect();
+
+    let size = Extent3d {
+        width: 2 * MORPH_COMPONENTS as u32,
+        height: 1,
+        depth_or_array_layers: 1,
+    };
+    let image = Image::new(size, TextureDimension::D3, data, TextureFormat::R32Float);
+
+    let positions = [[-1.0, 0.0, 0.0], [1.0, 0.0, 0.0]];
+    let split = split_image(&image, &[(0, 0.5)], &positions).unwrap();
+    assert_eq!(split.texture_descriptor.size.depth_or_array_layers, 3);
+
+    let floats: Vec<f32> = split
+        .data
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+        .collect();
+    let layer = 2 * MORPH_COMPONENTS;
+    // Positive X layer, then negative X layer.
+    assert_eq!(floats[layer + 1], 0.0);
+    assert_eq!(floats[layer + MORPH_COMPONENTS + 1], 1.0);
+    assert_eq!(floats[2 * layer + 1], 1.0);
+    assert_eq!(floats[2 * layer + MORPH_COMPONENTS + 1], 0.0);
+
+    assert_eq!(positive_factor(0.0, 0.0), 0.5);
+    assert_eq!(corrective_weight([0.5, 0.5]), 0.25);
+}
@@ -1,41 +1,31 @@
+use crate::lip_sync::{LipSyncSource, MouthShape};
 use crate::morph_viewer_plugin::WeightsControl;
+use crate::vrm_gltf::{Meta, VrmDocument};
+use crate::vrm_meta::{check_permissions, UsagePolicy, ViolationAction};
+use bevy::utils::HashMap;
 use bevy::{
     ecs::entity::{EntityMapper, MapEntities},
     gltf::GltfExtras,
     prelude::*,
-    render::mesh::morph::MeshMorphWeights,
     render::mesh::skinning::SkinnedMesh,
 };
 use bevy_xpbd_3d::{math::*, prelude::*, SubstepSchedule, SubstepSet};
-use gltf::Gltf;
-use kira::{
-    manager::{backend::DefaultBackend, AudioManager, AudioManagerSettings},
-    sound::static_sound::{StaticSoundData, StaticSoundSettings},
-};
-use rlip_sync::lip_sync::*;
 use serde;
-use std::cmp::{max, min};
 use std::f32::consts::PI;
-use std::time::SystemTime;
 
 pub struct VrmPlugin;
 
-/// In Hz.
-const LIP_SYNC_SAMPLE_RATE: u32 = 20;
-
-/// In ms.
-const LIP_SYNC_SAMPLE_RANGE_LENGTH: u32 = 100;
-
 impl Plugin for VrmPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, (setup))
+        // `setup` checks avatars against it, with or without `VrmMetaPlugin`.
+        app.init_resource::<UsagePolicy>()
+            .add_systems(Startup, (setup))
             .add_systems(
                 Update,
                 (
-                    setup_morphs,
                     setup_animations,
                     setup_spring_bones,
-                    update_shape,
+                    apply_mouth_shapes,
                     blow_wind,
                 ),
             )
@@ -53,73 +43,49 @@ impl Plugin for VrmPlugin {
     }
 }
 
-#[derive(Resource)]
-struct SpeechAudio {
-    pub sound_data: StaticSoundData,
-    pub lip_sync: LipSync,
-    pub start_time: SystemTime,
-    pub last_time_handled: u128,
-    pub playing: bool,
-}
-
 #[derive(Component)]
-struct MySpeechAudio;
-
-#[derive(Component)]
-struct VrmData {
+pub(crate) struct VrmData {
     pub spring_bone_roots: Vec<String>,
     // anim: Handle<AnimationClip>,
     // mesh: Handle<Mesh>,
     pub shape_keys: ShapeKeys,
+    /// All blend shape groups, in the order of the file.
+    pub shape_key_groups: Vec<ShapeKeyGroup>,
+    pub meta: Meta,
+    /// Asset path of the glTF file.
+    pub path: String,
+    /// The thumbnail embedded in the file, loaded as the `#Texture{n}` sub-asset of the glTF.
+    pub thumbnail: Option<Handle<Image>>,
 }
 
-struct ShapeKeys {
-    a: u32,
-    i: u32,
-    u: u32,
-    e: u32,
-    o: u32,
+/// Binds of the A, I, U, E and O blend shape groups, with the weight of each bind from 0 to 1.
+/// A group often binds several meshes, like the face and the teeth.
+#[derive(Default)]
+pub(crate) struct ShapeKeys {
+    vowels: [Vec<(ShapeKey, f32)>; 5],
 }
 
-fn setup(asset_server: Res<AssetServer>, mut commands: Commands) {
-    // Create an audio manager, which plays sounds and manages resources.
-    let mut manager = AudioManager::<DefaultBackend>::new(AudioManagerSettings::default()).unwrap();
-
-    // Add speech audio for lip sync.
-    commands.spawn((
-        AudioBundle {
-            source: asset_server.load("sounds/sound.ogg"),
-            settings: PlaybackSettings {
-                paused: true,
-                ..default()
-            },
-        },
-        MySpeechAudio,
-    ));
-
-    let sound_data =
-        match StaticSoundData::from_file("assets/sounds/sound.ogg", StaticSoundSettings::default())
-        {
-            Ok(data) => {
-                println!("Loaded audio file.");
-                data
-            }
-            Err(error) => {
-                println!("Error loading audio file: {:?}", error);
-                panic!();
-            }
-        };
+#[derive(Clone)]
+pub(crate) struct ShapeKey {
+    /// Name of the glTF node with the mesh.
+    pub node: String,
+    pub index: u32,
+}
 
-    let mut lip_sync = LipSync::new();
+impl ShapeKey {
+    /// Whether this is morph target `index` of the mesh entity named `name`.
+    pub fn matches(&self, name: &Name, index: usize) -> bool {
+        self.node == name.as_str() && self.index as usize == index
+    }
+}
 
-    commands.insert_resource(SpeechAudio {
-        sound_data,
-        lip_sync,
-        start_time: SystemTime::now(),
-        last_time_handled: 0,
-        playing: false,
-    });
+/// A VRM blend shape group, with the weight of each bind from 0 to 1.
+pub(crate) struct ShapeKeyGroup {
+    pub name: String,
+    pub binds: Vec<(ShapeKey, f32)>,
+}
 
+fn setup(asset_server: Res<AssetServer>, policy: Res<UsagePolicy>, mut commands: Commands) {
     let gltf_filename = "AvatarSample_A.glb";
 
     // commands.insert_resource(MorphData {
@@ -127,72 +93,128 @@ fn setup(asset_server: Res<AssetServer>, mut commands: Commands) {
     //     mesh: asset_server.load(format!("models/{}#Mesh1/Primitive0", gltf_filename)),
     // });
 
-    let mut vrm_scene = commands.spawn(SceneBundle {
+    let path = format!("models/{}", gltf_filename);
+
+    let scene = SceneBundle {
         scene: asset_server.load(format!("models/{}#Scene0", gltf_filename)),
         ..default()
-    });
+    };
 
-    {
-        let gltf: Gltf<crate::vrm_gltf::GltfExtensions> =
-            Gltf::open(format!("assets/models/{}", gltf_filename)).unwrap();
+    match VrmDocument::open(format!("assets/models/{}", gltf_filename)) {
+        Ok(document) => {
+            let violations = check_permissions(&document.vrm.meta, &policy);
+            for violation in &violations {
+                println!("{}: {}", gltf_filename, violation);
+            }
+            if !violations.is_empty() && policy.on_violation == ViolationAction::Refuse {
+                println!("Refused to load {}", gltf_filename);
+                return;
+            }
 
-        let json = gltf.document.as_json();
+            let nodes: Vec<_> = document
+                .gltf
+                .document
+                .nodes()
+                .map(|node| (node.index(), node.name().unwrap_or_default()))
+                .collect();
+
+            // Node names as given by the glTF loader, by mesh index.
+            let mut mesh_nodes = HashMap::new();
+            for node in document.gltf.document.nodes() {
+                if let Some(mesh) = node.mesh() {
+                    let name = match node.name() {
+                        Some(name) => name.to_string(),
+                        None => format!("GltfNode{}", node.index()),
+                    };
+                    mesh_nodes.entry(mesh.index() as u32).or_insert(name);
+                }
+            }
 
-        let mut nodes = vec![];
-        for node in gltf.document.nodes() {
-            nodes.push((node.index(), node.name().unwrap()));
-        }
+            let vrm = &document.vrm;
+
+            let mut spring_bone_roots = vec![];
+
+            let mut shape_keys = ShapeKeys::default();
+            let mut shape_key_groups = vec![];
+
+            for shape_group in &vrm.blend_shape_master.blend_shape_groups {
+                let binds: Vec<(ShapeKey, f32)> = shape_group
+                    .binds
+                    .iter()
+                    .filter_map(|bind| {
+                        let Some(node) = mesh_nodes.get(&bind.mesh) else {
+                            println!(
+                                "Blend shape {} refers to missing mesh {}",
+                                shape_group.name, bind.mesh
+                            );
+                            return None;
+                        };
+                        let shape_key = ShapeKey {
+                            node: node.clone(),
+                            index: bind.index,
+                        };
+                        Some((shape_key, bind.weight / 100.0))
+                    })
+                    .collect();
+
+                let vowel = match shape_group.name.as_str() {
+                    "A" => Some(0),
+                    "I" => Some(1),
+                    "U" => Some(2),
+                    "E" => Some(3),
+                    "O" => Some(4),
+                    _ => None,
+                };
+                if let Some(vowel) = vowel {
+                    shape_keys.vowels[vowel] = binds.clone();
+                }
 
-        // println!("glTF json:\n{:?}", json.to_string().unwrap());
+                shape_key_groups.push(ShapeKeyGroup {
+                    name: shape_group.name.clone(),
+                    binds,
+                });
+            }
 
-        if let Some(extensions) = json.extensions.as_ref() {
-            if let Some(vrm) = extensions.custom.vrm.as_ref() {
-                let mut spring_bone_roots = vec![];
+            for bone_group in &vrm.secondary_animation.bone_groups {
+                for bone_index in &bone_group.bones {
+                    let Some(bone) = nodes.get(*bone_index as usize) else {
+                        println!("Spring bone refers to missing node {}", bone_index);
+                        continue;
+                    };
 
-                let mut shape_keys = ShapeKeys {
-                    a: 0,
-                    i: 0,
-                    u: 0,
-                    e: 0,
-                    o: 0,
-                };
+                    spring_bone_roots.push(bone.1.to_string());
 
-                for shape_group in &vrm.blend_shape_master.blend_shape_groups {
-                    match shape_group.name.as_str() {
-                        "A" => {
-                            shape_keys.a = shape_group.binds.get(0).unwrap().index;
-                        }
-                        "I" => {
-                            shape_keys.i = shape_group.binds.get(0).unwrap().index;
-                        }
-                        "U" => {
-                            shape_keys.u = shape_group.binds.get(0).unwrap().index;
-                        }
-                        "E" => {
-                            shape_keys.e = shape_group.binds.get(0).unwrap().index;
-                        }
-                        "O" => {
-                            shape_keys.o = shape_group.binds.get(0).unwrap().index;
-                        }
-                        _ => {}
-                    }
+                    println!("{:?} {:?}", bone.0, bone.1);
                 }
+            }
 
-                for bone_group in &vrm.secondary_animation.bone_groups {
-                    for bone_index in &bone_group.bones {
-                        let bone = nodes[*bone_index as usize];
-
-                        spring_bone_roots.push(bone.1.to_string());
+            // Cued for the speech controls to play.
+            let mut source = LipSyncSource::default();
+            source.cue(asset_server.load("sounds/sound.ogg"));
 
-                        println!("{:?} {:?}", bone.0, bone.1);
-                    }
-                }
+            let thumbnail = vrm
+                .meta
+                .texture
+                .map(|texture| asset_server.load(format!("{}#Texture{}", path, texture)));
 
-                vrm_scene.insert(VrmData {
+            commands.spawn((
+                scene,
+                VrmData {
                     spring_bone_roots,
                     shape_keys,
-                });
-            }
+                    shape_key_groups,
+                    meta: vrm.meta.clone(),
+                    path,
+                    thumbnail,
+                },
+                source,
+                MouthShape::default(),
+            ));
+        }
+        Err(error) => {
+            println!("Error loading VRM data from {}: {}", gltf_filename, error);
+
+            commands.spawn(scene);
         }
     }
 }
@@ -217,140 +239,31 @@ fn setup_animations(mut has_setup: Local<bool>, mut players: Query<(&Name, &mut
     // }
 }
 
-fn update_shape(
-    controls: Option<ResMut<WeightsControl>>,
-    mut speech_audio: ResMut<SpeechAudio>,
-    mut morphs: Query<&mut MorphWeights>,
-    vrm_query: Query<&VrmData>,
-    time: Res<Time>,
+/// Writes the mouth shapes of avatars to the weights of their A, I, U, E and O morph targets.
+fn apply_mouth_shapes(
+    mut avatars: Query<(&VrmData, &MouthShape, &mut WeightsControl), Changed<MouthShape>>,
+    name_query: Query<&Name>,
 ) {
-    if !speech_audio.playing {
-        return;
-    }
-
-    let vrm = vrm_query.get_single();
-
-    match speech_audio.start_time.elapsed() {
-        Ok(elapsed) => {
-            let current_time = elapsed.as_millis();
-
-            let sample_interval_in_ms = (1000.0 / LIP_SYNC_SAMPLE_RATE as f32) as u128;
-
-            if current_time > (speech_audio.last_time_handled + sample_interval_in_ms) {
-                // println!("Time: {:?} {:?} {:?}", current_time, sample_interval_in_ms, speech_audio.last_time_handled);
-
-                let current_time_in_sec = current_time as f32 / 1000.0;
-                let half_sample_range_length_in_sec =
-                    LIP_SYNC_SAMPLE_RANGE_LENGTH as f32 / 1000.0 * 0.5;
-
-                // The range to sample.
-                let frame_range = (
-                    max(
-                        ((current_time_in_sec - half_sample_range_length_in_sec)
-                            * speech_audio.sound_data.sample_rate as f32)
-                            as usize,
-                        0,
-                    ),
-                    min(
-                        ((current_time_in_sec + half_sample_range_length_in_sec)
-                            * speech_audio.sound_data.sample_rate as f32)
-                            as usize,
-                        speech_audio.sound_data.frames.len(),
-                    ),
-                );
-
-                let mut stream = Vec::new();
-
-                for frame_index in frame_range.0..frame_range.1 {
-                    stream.push(speech_audio.sound_data.frames[frame_index].left);
-                }
-
-                speech_audio.lip_sync.update(stream);
-                let res = speech_audio.lip_sync.poll();
-                if let Some(estimate) = res {
-                    // println!("{:?}", estimate);
-
-                    let Some(mut controls) = controls else {
-                        return;
-                    };
-
-                    let Ok(vrm) = vrm else {
-                        return;
-                    };
-
-                    for target in &mut controls.weights {
-                        target.weight = 0.0;
-                    }
-
-                    match estimate.vowel {
-                        0 => {
-                            controls.weights[vrm.shape_keys.a as usize].weight = estimate.amount;
-                        }
-                        1 => {
-                            controls.weights[vrm.shape_keys.i as usize].weight = estimate.amount;
-                        }
-                        2 => {
-                            controls.weights[vrm.shape_keys.u as usize].weight = estimate.amount;
-                        }
-                        3 => {
-                            controls.weights[vrm.shape_keys.e as usize].weight = estimate.amount;
-                        }
-                        4 => {
-                            controls.weights[vrm.shape_keys.o as usize].weight = estimate.amount;
-                        }
-                        _ => {}
+    for (vrm, mouth, mut controls) in &mut avatars {
+        for target in &mut controls.weights {
+            let Ok(name) = name_query.get(target.entity) else {
+                continue;
+            };
+
+            // Summed, in case several groups bind the same morph target.
+            let mut weight = None;
+            for (binds, vowel_weight) in vrm.shape_keys.vowels.iter().zip(mouth.0) {
+                for (shape_key, bind_weight) in binds {
+                    if shape_key.matches(name, target.index) {
+                        *weight.get_or_insert(0.0) += vowel_weight * bind_weight;
                     }
                 }
-
-                speech_audio.last_time_handled = current_time;
             }
-        }
-        Err(e) => {
-            println!("Error: {e:?}");
-        }
-    }
-}
-
-fn pause(keyboard_input: Res<Input<KeyCode>>) {
-    if keyboard_input.just_pressed(KeyCode::Space) {}
-}
-
-/// You can get the morph target names in their corresponding [`Mesh`].
-/// They are in the order of the weights.
-fn setup_morphs(
-    mut has_setup: Local<bool>,
-    meshes: Res<Assets<Mesh>>,
-    morph_target_weights: Query<&MeshMorphWeights>,
-    music_controller: Query<&AudioSink, With<MySpeechAudio>>,
-    mut speech_audio: ResMut<SpeechAudio>,
-) {
-    if *has_setup {
-        return;
-    }
-
-    // let Some(mesh) = meshes.get(&morph_data.mesh) else {
-    //     if let Ok(sink) = music_controller.get_single() {
-    //         sink.pause();
-    //     }
-    //     return;
-    // };
-
-    for weight in &morph_target_weights {
-        // let Some(names) = mesh.morph_target_names() else {
-        //     continue;
-        // };
-
-        // println!("Shape keys: {:?}", names);
 
-        speech_audio.start_time = SystemTime::now();
-        speech_audio.playing = true;
-        if let Ok(sink) = music_controller.get_single() {
-            sink.play();
+            if let Some(weight) = weight {
+                target.weight = f32::min(weight, 1.0);
+            }
         }
-
-        *has_setup = true;
-
-        break;
     }
 }
 
@@ -1,79 +1,29 @@
-//! Enable controls for morph targets detected in a loaded scene.
+//! Enable controls for morph targets detected in loaded scenes.
 //!
-//! Collect morph targets and assign keys to them,
+//! Collect morph targets into a [`WeightsControl`] on each scene root and assign keys to them,
 //! shows on screen additional controls for morph targets.
+//! Keys come from the [`MorphKeyMap`], with page keys to reach the targets beyond its length.
+//! They're ignored while typing into egui, e.g. into the panel's search field.
+//! Each target is controlled in one [`ControlMode`]; alt and its key switch to the next mode.
+//! The applied weights follow the targets by their [`Interpolation`].
 //!
 //! Illustrates how to access and modify individual morph target weights.
 //! See the [`update_morphs`] system for details.
 //!
 //! Also illustrates how to read morph target names in [`detect_morphs`].
 
+use crate::morph_keys::{keyboard_free, MorphKeyMap, MorphKeysPlugin};
 use bevy::prelude::*;
+use bevy::scene::SceneInstance;
+use bevy::utils::{HashMap, HashSet};
 use bevy_xpbd_3d::parry::na::clamp;
 use std::cmp::max;
 use std::fmt;
 
-const WEIGHT_PER_SECOND: f32 = 20.0;
-const ALL_MODIFIERS: &[KeyCode] = &[KeyCode::ShiftLeft, KeyCode::ControlLeft, KeyCode::AltLeft];
-const AVAILABLE_KEYS: [MorphKey; 56] = [
-    MorphKey::new("r", &[], KeyCode::R),
-    MorphKey::new("t", &[], KeyCode::T),
-    MorphKey::new("z", &[], KeyCode::Z),
-    MorphKey::new("i", &[], KeyCode::I),
-    MorphKey::new("o", &[], KeyCode::O),
-    MorphKey::new("p", &[], KeyCode::P),
-    MorphKey::new("f", &[], KeyCode::F),
-    MorphKey::new("g", &[], KeyCode::G),
-    MorphKey::new("h", &[], KeyCode::H),
-    MorphKey::new("j", &[], KeyCode::J),
-    MorphKey::new("k", &[], KeyCode::K),
-    MorphKey::new("y", &[], KeyCode::Y),
-    MorphKey::new("x", &[], KeyCode::X),
-    MorphKey::new("c", &[], KeyCode::C),
-    MorphKey::new("v", &[], KeyCode::V),
-    MorphKey::new("b", &[], KeyCode::B),
-    MorphKey::new("n", &[], KeyCode::N),
-    MorphKey::new("m", &[], KeyCode::M),
-    MorphKey::new("0", &[], KeyCode::Key0),
-    MorphKey::new("1", &[], KeyCode::Key1),
-    MorphKey::new("2", &[], KeyCode::Key2),
-    MorphKey::new("3", &[], KeyCode::Key3),
-    MorphKey::new("4", &[], KeyCode::Key4),
-    MorphKey::new("5", &[], KeyCode::Key5),
-    MorphKey::new("6", &[], KeyCode::Key6),
-    MorphKey::new("7", &[], KeyCode::Key7),
-    MorphKey::new("8", &[], KeyCode::Key8),
-    MorphKey::new("9", &[], KeyCode::Key9),
-    MorphKey::new("lshift-R", &[KeyCode::ShiftLeft], KeyCode::R),
-    MorphKey::new("lshift-T", &[KeyCode::ShiftLeft], KeyCode::T),
-    MorphKey::new("lshift-Z", &[KeyCode::ShiftLeft], KeyCode::Z),
-    MorphKey::new("lshift-I", &[KeyCode::ShiftLeft], KeyCode::I),
-    MorphKey::new("lshift-O", &[KeyCode::ShiftLeft], KeyCode::O),
-    MorphKey::new("lshift-P", &[KeyCode::ShiftLeft], KeyCode::P),
-    MorphKey::new("lshift-F", &[KeyCode::ShiftLeft], KeyCode::F),
-    MorphKey::new("lshift-G", &[KeyCode::ShiftLeft], KeyCode::G),
-    MorphKey::new("lshift-H", &[KeyCode::ShiftLeft], KeyCode::H),
-    MorphKey::new("lshift-J", &[KeyCode::ShiftLeft], KeyCode::J),
-    MorphKey::new("lshift-K", &[KeyCode::ShiftLeft], KeyCode::K),
-    MorphKey::new("lshift-Y", &[KeyCode::ShiftLeft], KeyCode::Y),
-    MorphKey::new("lshift-X", &[KeyCode::ShiftLeft], KeyCode::X),
-    MorphKey::new("lshift-C", &[KeyCode::ShiftLeft], KeyCode::C),
-    MorphKey::new("lshift-V", &[KeyCode::ShiftLeft], KeyCode::V),
-    MorphKey::new("lshift-B", &[KeyCode::ShiftLeft], KeyCode::B),
-    MorphKey::new("lshift-N", &[KeyCode::ShiftLeft], KeyCode::N),
-    MorphKey::new("lshift-M", &[KeyCode::ShiftLeft], KeyCode::M),
-    MorphKey::new("lshift-0", &[KeyCode::ShiftLeft], KeyCode::Key0),
-    MorphKey::new("lshift-1", &[KeyCode::ShiftLeft], KeyCode::Key1),
-    MorphKey::new("lshift-2", &[KeyCode::ShiftLeft], KeyCode::Key2),
-    MorphKey::new("lshift-3", &[KeyCode::ShiftLeft], KeyCode::Key3),
-    MorphKey::new("lshift-4", &[KeyCode::ShiftLeft], KeyCode::Key4),
-    MorphKey::new("lshift-5", &[KeyCode::ShiftLeft], KeyCode::Key5),
-    MorphKey::new("lshift-6", &[KeyCode::ShiftLeft], KeyCode::Key6),
-    MorphKey::new("lshift-7", &[KeyCode::ShiftLeft], KeyCode::Key7),
-    MorphKey::new("lshift-8", &[KeyCode::ShiftLeft], KeyCode::Key8),
-    MorphKey::new("lshift-9", &[KeyCode::ShiftLeft], KeyCode::Key9),
-];
-
+/// Half-life of the default smoothing, in seconds.
+pub const DEFAULT_HALF_LIFE: f32 = 0.035;
+/// How fast ramping targets change, in weight per second.
+const RAMP_PER_SECOND: f32 = 1.0;
 #[derive(Clone, Copy)]
 pub enum WeightChange {
     Increase,
@@ -104,6 +54,97 @@ impl WeightChange {
     }
 }
 
+/// How the key of a target changes its weight.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ControlMode {
+    /// Fully on while the key is held.
+    #[default]
+    Hold,
+    /// Switched on and off by presses.
+    Toggle,
+    /// Presses start and stop moving the weight back and forth.
+    Ramp,
+}
+
+impl ControlMode {
+    fn next(self) -> Self {
+        match self {
+            ControlMode::Hold => ControlMode::Toggle,
+            ControlMode::Toggle => ControlMode::Ramp,
+            ControlMode::Ramp => ControlMode::Hold,
+        }
+    }
+}
+
+impl fmt::Display for ControlMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ControlMode::Hold => write!(f, "hold"),
+            ControlMode::Toggle => write!(f, "toggle"),
+            ControlMode::Ramp => write!(f, "ramp"),
+        }
+    }
+}
+
+/// How the applied weight of a target follows its set weight.
+/// All are evaluated exactly for the frame time, so they don't depend on the frame rate.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interpolation {
+    Instant,
+    /// Closes half of the distance every half-life, in seconds.
+    Exponential {
+        half_life: f32,
+    },
+    /// A critically damped spring, which doesn't overshoot but keeps its velocity when the
+    /// target moves. Half-life in seconds.
+    Spring {
+        half_life: f32,
+    },
+}
+
+impl Default for Interpolation {
+    fn default() -> Self {
+        Interpolation::Exponential {
+            half_life: DEFAULT_HALF_LIFE,
+        }
+    }
+}
+
+impl Interpolation {
+    /// Moves `value` towards `target` for `delta` seconds, updating `velocity` for springs.
+    pub fn step(self, value: f32, velocity: &mut f32, target: f32, delta: f32) -> f32 {
+        match self {
+            Interpolation::Exponential { half_life } if half_life > 0.0 => {
+                *velocity = 0.0;
+                target + (value - target) * 0.5f32.powf(delta / half_life)
+            }
+            Interpolation::Spring { half_life } if half_life > 0.0 => {
+                let y = 2.0 * std::f32::consts::LN_2 / half_life;
+                let j0 = value - target;
+                let j1 = *velocity + j0 * y;
+                let decay = (-y * delta).exp();
+
+                *velocity = decay * (*velocity - j1 * y * delta);
+                decay * (j0 + j1 * delta) + target
+            }
+            _ => {
+                *velocity = 0.0;
+                target
+            }
+        }
+    }
+}
+
+impl fmt::Display for Interpolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Interpolation::Instant => write!(f, "instant"),
+            Interpolation::Exponential { .. } => write!(f, "exponential"),
+            Interpolation::Spring { .. } => write!(f, "spring"),
+        }
+    }
+}
+
 #[derive(Component)]
 struct MorphTargetLabel {}
 
@@ -114,6 +155,17 @@ pub struct Target {
     pub index: usize,
     pub weight: f32,
     pub change_dir: WeightChange,
+    pub mode: ControlMode,
+    /// Whether the key of a target in [`ControlMode::Hold`] is held,
+    /// or a target in [`ControlMode::Ramp`] is moving.
+    pub active: bool,
+    /// While any target is soloed, only soloed targets are applied.
+    pub solo: bool,
+    /// Applied as 0, keeping the weight.
+    pub muted: bool,
+    pub interpolation: Interpolation,
+    /// Of the applied weight, per second.
+    velocity: f32,
 }
 
 impl fmt::Display for Target {
@@ -129,8 +181,11 @@ impl fmt::Display for Target {
 }
 
 impl Target {
-    fn text_section(&self, key: &str, style: TextStyle) -> TextSection {
-        TextSection::new(format!("[{key}] {self}\n"), style)
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+    pub fn entity_name(&self) -> Option<&str> {
+        self.entity_name.as_deref()
     }
     fn new(
         entity_name: Option<&Name>,
@@ -150,51 +205,107 @@ impl Target {
                 index,
                 weight: *weight,
                 change_dir: WeightChange::Increase,
+                mode: ControlMode::default(),
+                active: false,
+                solo: false,
+                muted: false,
+                interpolation: Interpolation::default(),
+                velocity: 0.0,
             })
             .collect()
     }
 }
 
-#[derive(Resource)]
+/// The morph targets of a scene, on its root entity.
+#[derive(Component)]
 pub struct WeightsControl {
     pub weights: Vec<Target>,
 }
 
-struct MorphKey {
-    name: &'static str,
-    modifiers: &'static [KeyCode],
-    key: KeyCode,
-}
-
-impl MorphKey {
-    const fn new(name: &'static str, modifiers: &'static [KeyCode], key: KeyCode) -> Self {
-        MorphKey {
-            name,
-            modifiers,
-            key,
+impl WeightsControl {
+    /// Sets all weights to 0 and clears modes, solo and mute.
+    pub fn reset(&mut self) {
+        for target in &mut self.weights {
+            target.weight = 0.0;
+            target.mode = ControlMode::default();
+            target.active = false;
+            target.solo = false;
+            target.muted = false;
         }
     }
-    fn active(&self, inputs: &Input<KeyCode>) -> bool {
-        let mut modifier = self.modifiers.iter();
-        let mut non_modifier = ALL_MODIFIERS.iter().filter(|m| !self.modifiers.contains(m));
-
-        let key = inputs.pressed(self.key);
-        let modifier = modifier.all(|m| inputs.pressed(*m));
-        let non_modifier = non_modifier.all(|m| !inputs.pressed(*m));
-        key && modifier && non_modifier
-    }
+}
+
+/// The targets of all scenes, in the order of their root entities, for the key bindings.
+fn sorted_controls<'a>(
+    controls: impl Iterator<Item = (Entity, &'a WeightsControl)>,
+) -> Vec<&'a WeightsControl> {
+    let mut controls: Vec<_> = controls.collect();
+    controls.sort_by_key(|(root, _)| *root);
+    controls.into_iter().map(|(_, control)| control).collect()
+}
+
+fn spawn_text(mut commands: Commands, key_map: Res<MorphKeyMap>) {
+    let style = TextStyle::default();
+    let mut sections = vec![
+        TextSection::new("Morph Target Controls\n", style.clone()),
+        TextSection::new("---------------\n", style.clone()),
+    ];
+    // One section per key, showing the targets of the current page.
+    sections.extend(
+        key_map
+            .bindings
+            .iter()
+            .map(|_| TextSection::new("", style.clone())),
+    );
+    commands.spawn((
+        TextBundle::from_sections(sections).with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(10.0),
+            left: Val::Px(10.0),
+            ..default()
+        }),
+        MorphTargetLabel {},
+    ));
 }
 
 fn update_text(
-    controls: Option<Res<WeightsControl>>,
+    controls: Query<(Entity, &WeightsControl)>,
+    key_map: Res<MorphKeyMap>,
     mut text: Query<&mut Text, With<MorphTargetLabel>>,
     morphs: Query<&MorphWeights>,
 ) {
-    let Some(controls) = controls else {
+    let Ok(mut text) = text.get_single_mut() else {
         return;
     };
 
-    for (i, target) in controls.weights.iter().enumerate() {
+    let controls = sorted_controls(controls.iter());
+    let all_targets: Vec<&Target> = controls
+        .iter()
+        .flat_map(|control| control.weights.iter())
+        .collect();
+
+    let count = all_targets.len();
+    text.sections[0].value = format!(
+        "Morph Target Controls (page {}/{}, {:?}/{:?}, alt+key: mode)\n",
+        key_map.page + 1,
+        key_map.page_count(count),
+        key_map.previous_page,
+        key_map.next_page
+    );
+
+    let targets = key_map.page_targets(count);
+
+    for (slot, section) in text.sections[2..].iter_mut().enumerate() {
+        let i = targets.start + slot;
+        let (true, Some(target), Some(binding)) = (
+            targets.contains(&i),
+            all_targets.get(i),
+            key_map.bindings.get(slot),
+        ) else {
+            section.value.clear();
+            continue;
+        };
+
         let Ok(weights) = morphs.get(target.entity) else {
             continue;
         };
@@ -208,114 +319,213 @@ fn update_text(
             "unnamed".to_string()
         };
 
-        let key_name = &AVAILABLE_KEYS[i].name;
-        let mut text = text.single_mut();
-        text.sections[i + 2].value = format!(
-            "[{key_name}]({shape_key_name}) {:.2}/{:.2}\n",
-            actual_weight, target.weight
+        section.value = format!(
+            "[{binding}]({shape_key_name}) {:.2}/{:.2} {}\n",
+            actual_weight, target.weight, target.mode
         );
     }
 }
 
-fn update_morphs(
-    controls: Option<ResMut<WeightsControl>>,
-    mut morphs: Query<&mut MorphWeights>,
+fn change_page(
+    controls: Query<&WeightsControl>,
+    mut key_map: ResMut<MorphKeyMap>,
     input: Res<Input<KeyCode>>,
-    time: Res<Time>,
 ) {
-    let Some(mut controls) = controls else {
-        return;
-    };
+    let count = controls.iter().map(|control| control.weights.len()).sum();
+    key_map.change_page(&input, count);
+}
 
-    let change = time.delta_seconds() * WEIGHT_PER_SECOND;
+/// Manually activates morph targets.
+fn handle_morph_keys(
+    mut controls: Query<(Entity, &mut WeightsControl)>,
+    key_map: Res<MorphKeyMap>,
+    input: Res<Input<KeyCode>>,
+) {
+    // Same order as `sorted_controls`.
+    let mut controls: Vec<_> = controls.iter_mut().collect();
+    controls.sort_by_key(|(root, _)| *root);
 
-    for (i, target) in controls.weights.iter_mut().enumerate() {
-        // Manually activate a morph target.
-        // if AVAILABLE_KEYS[i].active(&input) {
-        //     target.weight = 1.0;
-        // } else {
-        //     target.weight = 0.0;
-        // }
+    let targets = controls
+        .iter_mut()
+        .flat_map(|(_, control)| control.weights.iter_mut());
 
-        // Get the actual weights.
-        let Ok(mut weights) = morphs.get_mut(target.entity) else {
+    for (i, target) in targets.enumerate() {
+        // Only presses and releases change the weight, so other systems can drive it in between.
+        let Some(binding) = key_map.binding(i) else {
             continue;
         };
 
-        // To update individual morph target weights, get the `MorphWeights`
-        // component and call `weights_mut` to get access to the weights.
-        let weights_slice = weights.weights_mut();
+        if binding.mode_switch_pressed(&input) {
+            target.mode = target.mode.next();
+            target.active = false;
+            continue;
+        }
 
-        let i = target.index;
+        let pressed = binding.active(&input) && input.just_pressed(binding.key);
 
-        let new_weight = clamp(lerp(weights_slice[i], target.weight, change), 0.0, 1.0);
+        match target.mode {
+            ControlMode::Hold => {
+                if pressed {
+                    target.weight = 1.0;
+                    target.active = true;
+                } else if target.active && !input.pressed(binding.key) {
+                    target.weight = 0.0;
+                    target.active = false;
+                }
+            }
+            ControlMode::Toggle => {
+                if pressed {
+                    target.weight = if target.weight > 0.5 { 0.0 } else { 1.0 };
+                }
+            }
+            ControlMode::Ramp => {
+                if pressed {
+                    target.active = !target.active;
+                }
+            }
+        }
+    }
+}
+
+pub(crate) fn update_morphs(
+    mut controls: Query<&mut WeightsControl>,
+    mut morphs: Query<&mut MorphWeights>,
+    time: Res<Time>,
+) {
+    let delta = time.delta_seconds();
+    let ramp_change = time.delta_seconds() * RAMP_PER_SECOND;
+
+    for mut control in &mut controls {
+        let any_solo = control.weights.iter().any(|target| target.solo);
 
-        weights_slice[i] = new_weight;
+        for target in control.weights.iter_mut() {
+            if target.mode == ControlMode::Ramp && target.active {
+                target.weight = target
+                    .change_dir
+                    .change_weight(target.weight, ramp_change)
+                    .clamp(0.0, 1.0);
+            }
+
+            // Get the actual weights.
+            let Ok(mut weights) = morphs.get_mut(target.entity) else {
+                continue;
+            };
+
+            // To update individual morph target weights, get the `MorphWeights`
+            // component and call `weights_mut` to get access to the weights.
+            let weights_slice = weights.weights_mut();
+
+            let index = target.index;
+
+            let target_weight = if target.muted || (any_solo && !target.solo) {
+                0.0
+            } else {
+                target.weight
+            };
+
+            let new_weight = target.interpolation.step(
+                weights_slice[index],
+                &mut target.velocity,
+                target_weight,
+                delta,
+            );
+
+            weights_slice[index] = clamp(new_weight, 0.0, 1.0);
+        }
     }
 }
 
+/// Adds the targets of new [`MorphWeights`] to the [`WeightsControl`] of their scene root,
+/// and drops those of removed ones.
 fn detect_morphs(
     mut commands: Commands,
-    morphs: Query<(Entity, &MorphWeights, Option<&Name>)>,
+    added: Query<(Entity, &MorphWeights, Option<&Name>), Added<MorphWeights>>,
+    mut removed: RemovedComponents<MorphWeights>,
+    mut controls: Query<&mut WeightsControl>,
+    parents: Query<&Parent>,
+    scenes: Query<(), With<SceneInstance>>,
     meshes: Res<Assets<Mesh>>,
-    mut setup: Local<bool>,
-    asset_server: Res<AssetServer>,
 ) {
-    let no_morphing = morphs.iter().len() == 0;
-    if no_morphing {
-        return;
-    }
-    if !*setup {
-        *setup = true;
-    } else {
-        return;
+    let removed: HashSet<Entity> = removed.read().collect();
+    if !removed.is_empty() {
+        for mut control in &mut controls {
+            control
+                .weights
+                .retain(|target| !removed.contains(&target.entity));
+        }
     }
-    let mut detected = Vec::new();
 
-    for (entity, weights, name) in &morphs {
+    let mut detected: HashMap<Entity, Vec<Target>> = HashMap::new();
+
+    for (entity, weights, name) in &added {
+        // The closest scene root, or the top of the hierarchy outside of scenes.
+        let root = parents
+            .iter_ancestors(entity)
+            .find(|ancestor| scenes.contains(*ancestor))
+            .or_else(|| parents.iter_ancestors(entity).last())
+            .unwrap_or(entity);
+
         let target_names = weights
             .first_mesh()
             .and_then(|h| meshes.get(h))
             .and_then(|m| m.morph_target_names());
         let targets = Target::new(name, weights.weights(), target_names, entity);
-        detected.extend(targets);
+        detected.entry(root).or_default().extend(targets);
+    }
+
+    for (root, targets) in detected {
+        match controls.get_mut(root) {
+            Ok(mut control) => control.weights.extend(targets),
+            Err(_) => {
+                commands
+                    .entity(root)
+                    .insert(WeightsControl { weights: targets });
+            }
+        }
     }
-    detected.truncate(AVAILABLE_KEYS.len());
-    let style = TextStyle::default();
-    let mut sections = vec![
-        TextSection::new("Morph Target Controls\n", style.clone()),
-        TextSection::new("---------------\n", style.clone()),
-    ];
-    let target_to_text =
-        |(i, target): (usize, &Target)| target.text_section(AVAILABLE_KEYS[i].name, style.clone());
-    sections.extend(detected.iter().enumerate().map(target_to_text));
-    commands.insert_resource(WeightsControl { weights: detected });
-    commands.spawn((
-        TextBundle::from_sections(sections).with_style(Style {
-            position_type: PositionType::Absolute,
-            top: Val::Px(10.0),
-            left: Val::Px(10.0),
-            ..default()
-        }),
-        MorphTargetLabel {},
-    ));
 }
 
 pub struct MorphViewerPlugin;
 
 impl Plugin for MorphViewerPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(
-            Update,
-            (
-                update_morphs,
-                detect_morphs,
-                update_text.after(update_morphs),
-            ),
-        );
+        app.add_plugins(MorphKeysPlugin)
+            .add_systems(Startup, spawn_text)
+            .add_systems(
+                Update,
+                (
+                    handle_morph_keys
+                        .run_if(keyboard_free)
+                        .before(update_morphs),
+                    update_morphs,
+                    detect_morphs,
+                    change_page.run_if(keyboard_free),
+                    update_text.after(update_morphs).after(change_page),
+                ),
+            );
     }
 }
 
-fn lerp(start: f32, end: f32, elapsed: f32) -> f32 {
-    start + elapsed * (end - start)
+#[test]
+fn test_interpolation_is_frame_rate_independent() {
+    for interpolation in [
+        Interpolation::Exponential { half_life: 0.1 },
+        Interpolation::Spring { half_life: 0.1 },
+    ] {
+        let run = |steps: usize| {
+            let (mut value, mut velocity) = (0.0, 0.0);
+            for _ in 0..steps {
+                value = interpolation.step(value, &mut velocity, 1.0, 0.5 / steps as f32);
+            }
+            value
+        };
+
+        assert!((run(15) - run(120)).abs() < 1e-4, "{}", interpolation);
+    }
+
+    let mut velocity = 0.0;
+    assert_eq!(
+        Interpolation::Instant.step(0.2, &mut velocity, 0.7, 0.01),
+        0.7
+    );
 }
@@ -0,0 +1,237 @@
+//! Mouth shapes from phoneme timings, e.g. from text to speech or lip sync tools.
+//!
+//! Supported files:
+//! - JSON: `[{ "time": 0.12, "phoneme": "AA1" }, ...]` with ARPAbet phonemes or the vowels a, i, u, e and o.
+//! - Rhubarb TSV: `0.12\tD` lines with Rhubarb mouth shapes.
+//! - Papagayo MOHO: a `MohoSwitch1` line, then `12 AI` lines with frames at 24 fps, counted from 1.
+//!
+//! Each phoneme lasts until the next one.
+
+use crate::viseme::{VisemeKey, VisemeTimeline, Vowel};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Frame rate of Papagayo exports.
+const PAPAGAYO_FPS: f64 = 24.0;
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct PhonemeTiming {
+    /// In seconds.
+    pub time: f64,
+    pub phoneme: String,
+}
+
+#[derive(Debug)]
+pub enum PhonemeError {
+    Json(serde_json::Error),
+    /// A line that isn't a time followed by a phoneme.
+    InvalidLine {
+        line: usize,
+        text: String,
+    },
+}
+
+impl fmt::Display for PhonemeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PhonemeError::Json(error) => write!(f, "Invalid JSON: {}", error),
+            PhonemeError::InvalidLine { line, text } => {
+                write!(f, "Invalid phoneme timing on line {}: {:?}", line, text)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PhonemeError {}
+
+/// Which mouth shape each phoneme makes, `None` for a closed mouth.
+/// Phonemes are looked up in upper case, without ARPAbet stress digits.
+#[derive(Clone, Debug, Default)]
+pub struct PhonemeMap(pub HashMap<String, Option<(Vowel, f32)>>);
+
+impl PhonemeMap {
+    fn from_table(table: &[(&str, Option<(Vowel, f32)>)]) -> Self {
+        PhonemeMap(
+            table
+                .iter()
+                .map(|(phoneme, viseme)| (phoneme.to_string(), *viseme))
+                .collect(),
+        )
+    }
+
+    /// ARPAbet as used by CMUdict and most English text to speech, plus Japanese vowels.
+    pub fn arpabet() -> Self {
+        use Vowel::*;
+
+        let mut map = PhonemeMap::from_table(&[
+            ("AA", Some((A, 1.0))),
+            ("AE", Some((A, 0.8))),
+            ("AH", Some((A, 0.6))),
+            ("AO", Some((O, 1.0))),
+            ("AW", Some((A, 0.8))),
+            ("AY", Some((A, 0.8))),
+            ("EH", Some((E, 0.8))),
+            ("ER", Some((U, 0.6))),
+            ("EY", Some((E, 1.0))),
+            ("IH", Some((I, 0.8))),
+            ("IY", Some((I, 1.0))),
+            ("OW", Some((O, 0.8))),
+            ("OY", Some((O, 0.8))),
+            ("UH", Some((U, 0.8))),
+            ("UW", Some((U, 1.0))),
+            ("W", Some((U, 0.6))),
+            ("M", None),
+            ("B", None),
+            ("P", None),
+            ("SIL", None),
+            ("SP", None),
+            ("A", Some((A, 1.0))),
+            ("I", Some((I, 1.0))),
+            ("U", Some((U, 1.0))),
+            ("E", Some((E, 1.0))),
+            ("O", Some((O, 1.0))),
+            ("N", None),
+        ]);
+
+        // Remaining consonants leave the mouth slightly open.
+        for consonant in [
+            "CH", "D", "DH", "F", "G", "HH", "JH", "K", "L", "NG", "R", "S", "SH", "T", "TH", "V",
+            "Y", "Z", "ZH",
+        ] {
+            map.0.insert(consonant.to_string(), Some((I, 0.3)));
+        }
+
+        map
+    }
+
+    /// Mouth shapes A to H and X of Rhubarb Lip Sync.
+    pub fn rhubarb() -> Self {
+        use Vowel::*;
+
+        PhonemeMap::from_table(&[
+            ("A", None),
+            ("B", Some((I, 0.4))),
+            ("C", Some((E, 0.8))),
+            ("D", Some((A, 1.0))),
+            ("E", Some((O, 0.8))),
+            ("F", Some((U, 0.8))),
+            ("G", Some((I, 0.2))),
+            ("H", Some((A, 0.5))),
+            ("X", None),
+        ])
+    }
+
+    /// The Preston Blair phoneme set of Papagayo.
+    pub fn papagayo() -> Self {
+        use Vowel::*;
+
+        PhonemeMap::from_table(&[
+            ("AI", Some((A, 1.0))),
+            ("E", Some((E, 1.0))),
+            ("O", Some((O, 1.0))),
+            ("U", Some((U, 1.0))),
+            ("WQ", Some((U, 0.7))),
+            ("L", Some((A, 0.4))),
+            ("ETC", Some((I, 0.3))),
+            ("FV", Some((I, 0.2))),
+            ("MBP", None),
+            ("REST", None),
+        ])
+    }
+
+    /// Unknown phonemes close the mouth.
+    pub fn viseme(&self, phoneme: &str) -> Option<(Vowel, f32)> {
+        let phoneme = phoneme
+            .trim_end_matches(|c: char| c.is_ascii_digit())
+            .to_uppercase();
+
+        self.0.get(&phoneme).copied().flatten()
+    }
+
+    pub fn timeline(&self, timings: &[PhonemeTiming]) -> VisemeTimeline {
+        let mut keys: Vec<_> = timings
+            .iter()
+            .map(|timing| {
+                let (vowel, amount) = self.viseme(&timing.phoneme).unwrap_or((Vowel::A, 0.0));
+                VisemeKey {
+                    time: timing.time,
+                    vowel,
+                    amount,
+                }
+            })
+            .collect();
+
+        keys.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+        VisemeTimeline { keys }
+    }
+}
+
+pub fn parse_json(bytes: &[u8]) -> Result<Vec<PhonemeTiming>, PhonemeError> {
+    serde_json::from_slice(bytes).map_err(PhonemeError::Json)
+}
+
+/// Parses Rhubarb TSV or Papagayo MOHO files, returning the phoneme map for the format.
+pub fn parse_tsv(text: &str) -> Result<(Vec<PhonemeTiming>, PhonemeMap), PhonemeError> {
+    let mut lines = text.lines().enumerate().peekable();
+
+    let papagayo = lines
+        .peek()
+        .is_some_and(|(_, line)| line.trim() == "MohoSwitch1");
+    // Papagayo's first frame is 1.
+    let (map, first_frame, time_scale) = if papagayo {
+        lines.next();
+        (PhonemeMap::papagayo(), 1.0, 1.0 / PAPAGAYO_FPS)
+    } else {
+        (PhonemeMap::rhubarb(), 0.0, 1.0)
+    };
+
+    let mut timings = vec![];
+
+    for (index, line) in lines {
+        let text = line.trim();
+        if text.is_empty() || text.starts_with('#') {
+            continue;
+        }
+
+        let mut columns = text.split_whitespace();
+        let (Some(time), Some(phoneme)) = (columns.next(), columns.next()) else {
+            return Err(PhonemeError::InvalidLine {
+                line: index + 1,
+                text: text.to_string(),
+            });
+        };
+
+        let Ok(time) = time.parse::<f64>() else {
+            return Err(PhonemeError::InvalidLine {
+                line: index + 1,
+                text: text.to_string(),
+            });
+        };
+
+        timings.push(PhonemeTiming {
+            time: (time - first_frame).max(0.0) * time_scale,
+            phoneme: phoneme.to_string(),
+        });
+    }
+
+    Ok((timings, map))
+}
+
+#[test]
+fn test_phoneme_files() {
+    let (timings, map) = parse_tsv("MohoSwitch1\n1 MBP\n13 AI\n25 rest\n").unwrap();
+    // Frame 1 is the start, frame 13 half a second in.
+    assert_eq!(timings[0].time, 0.0);
+    assert_eq!(timings[1].time, 0.5);
+    let timeline = map.timeline(&timings);
+    assert_eq!(timeline.weights_at(0.6), [1.0, 0.0, 0.0, 0.0, 0.0]);
+    assert_eq!(timeline.weights_at(1.0), [0.0; 5]);
+
+    let timings = parse_json(br#"[{ "time": 0.0, "phoneme": "UW1" }]"#).unwrap();
+    let timeline = PhonemeMap::arpabet().timeline(&timings);
+    assert_eq!(timeline.weights_at(0.1), [0.0, 0.0, 1.0, 0.0, 0.0]);
+
+    assert!(parse_tsv("0.5\n").is_err());
+}
@@ -0,0 +1,131 @@
+//! Transport controls for the speech of [`LipSyncSource`]s.
+//!
+//! The "Speech" window has play/pause, stop, a seek bar, loop and volume per speaking entity.
+//! Its stream button feeds the clip to a [`LipSyncStream`](crate::lip_sync_stream::LipSyncStream)
+//! instead, which moves the mouth without playing the audio.
+//! Keys act on all of them: space plays or pauses, comma and period seek back and forth,
+//! and L toggles looping.
+
+use crate::lip_sync::LipSyncSource;
+use crate::lip_sync_stream::{StreamClip, StreamFile};
+use crate::morph_keys::keyboard_free;
+use bevy::prelude::*;
+use bevy_inspector_egui::bevy_egui::{egui, EguiContexts};
+
+/// In seconds.
+const SEEK_STEP: f64 = 5.0;
+
+fn control_speech_with_keys(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut sources: Query<&mut LipSyncSource>,
+) {
+    for mut source in &mut sources {
+        if keyboard_input.just_pressed(KeyCode::Space) {
+            if source.is_speaking() {
+                source.toggle_pause();
+            } else {
+                source.replay();
+            }
+        }
+
+        if let Some(position) = source.position() {
+            if keyboard_input.just_pressed(KeyCode::Comma) {
+                source.seek(position - SEEK_STEP);
+            }
+            if keyboard_input.just_pressed(KeyCode::Period) {
+                source.seek(position + SEEK_STEP);
+            }
+        }
+
+        if keyboard_input.just_pressed(KeyCode::L) {
+            let looping = !source.is_looping();
+            source.set_looping(looping);
+        }
+    }
+}
+
+fn show_speech_controls(
+    mut commands: Commands,
+    mut contexts: EguiContexts,
+    mut sources: Query<(Entity, Option<&Name>, &mut LipSyncSource, Has<StreamFile>)>,
+) {
+    if sources.is_empty() {
+        return;
+    }
+
+    egui::Window::new("Speech").show(contexts.ctx_mut(), |ui| {
+        for (entity, name, mut source, streaming) in &mut sources {
+            ui.push_id(entity, |ui| {
+                match name {
+                    Some(name) => ui.heading(name.as_str()),
+                    None => ui.heading(format!("{:?}", entity)),
+                };
+
+                ui.horizontal(|ui| {
+                    let playing = source.is_speaking() && !source.is_paused();
+                    if ui.button(if playing { "Pause" } else { "Play" }).clicked() {
+                        if source.is_speaking() {
+                            source.toggle_pause();
+                        } else {
+                            source.replay();
+                        }
+                    }
+
+                    if ui.button("Stop").clicked() {
+                        source.stop();
+                    }
+
+                    let mut looping = source.is_looping();
+                    if ui.checkbox(&mut looping, "Loop").changed() {
+                        source.set_looping(looping);
+                    }
+
+                    let idle = !source.is_speaking() && !streaming;
+                    let stream = ui.add_enabled(idle, egui::Button::new("Stream"));
+                    if let (true, Some(clip)) = (stream.clicked(), source.last_clip()) {
+                        commands.entity(entity).insert(StreamClip(clip.clone()));
+                    }
+                });
+
+                match (source.position(), source.duration()) {
+                    (Some(position), Some(duration)) => {
+                        let mut seek_to = position;
+                        let slider = egui::Slider::new(&mut seek_to, 0.0..=duration)
+                            .suffix(" s")
+                            .fixed_decimals(1);
+                        if ui.add(slider).changed() {
+                            source.seek(seek_to);
+                        }
+                    }
+                    _ => {
+                        ui.label("Not speaking");
+                    }
+                }
+
+                let mut volume = source.volume();
+                if ui
+                    .add(egui::Slider::new(&mut volume, 0.0..=2.0).text("Volume"))
+                    .changed()
+                {
+                    source.set_volume(volume);
+                }
+            });
+
+            ui.separator();
+        }
+    });
+}
+
+pub struct SpeechControlsPlugin;
+
+impl Plugin for SpeechControlsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                control_speech_with_keys.run_if(keyboard_free),
+                show_speech_controls,
+            ),
+        );
+    }
+}
@@ -0,0 +1,556 @@
+//! Mouth shapes estimated from speech, and timelines of them baked ahead of time.
+//!
+//! Doesn't depend on Bevy, so the baking tool can use it too.
+
+use kira::sound::static_sound::StaticSoundData;
+use rlip_sync::lip_sync::LipSync;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::ops::Range;
+
+/// Sample rate the `rlip_sync` analyser is tuned for, in Hz.
+pub const ANALYSIS_SAMPLE_RATE: u32 = 44_100;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct AnalysisSettings {
+    /// How often the mouth shape is estimated, in Hz.
+    pub update_rate: f64,
+    /// Length of the audio analysed per estimate, in seconds.
+    pub window_length: f64,
+}
+
+impl Default for AnalysisSettings {
+    fn default() -> Self {
+        AnalysisSettings {
+            update_rate: 20.0,
+            window_length: 0.1,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Vowel {
+    A,
+    I,
+    U,
+    E,
+    O,
+}
+
+impl Vowel {
+    pub const ALL: [Vowel; 5] = [Vowel::A, Vowel::I, Vowel::U, Vowel::E, Vowel::O];
+
+    /// The vowel of an `rlip_sync` estimate.
+    pub fn from_index(index: usize) -> Option<Vowel> {
+        Vowel::ALL.get(index).copied()
+    }
+
+    pub fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Weights of the A, I, U, E and O mouth shapes, with only `vowel` open.
+pub fn vowel_weights(vowel: Vowel, amount: f32) -> [f32; 5] {
+    let mut weights = [0.0; 5];
+    weights[vowel.index()] = amount;
+    weights
+}
+
+/// Averages the channels of interleaved `samples` into one.
+pub fn downmix(samples: &[f32], channels: usize) -> Vec<f32> {
+    if channels <= 1 {
+        return samples.to_vec();
+    }
+
+    samples
+        .chunks_exact(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect()
+}
+
+/// The frames of decoded audio mixed down to mono, the way playback, baking and tests analyse it.
+pub fn mono_samples(sound_data: &StaticSoundData) -> Vec<f32> {
+    let frames: Vec<f32> = sound_data
+        .frames
+        .iter()
+        .flat_map(|frame| [frame.left, frame.right])
+        .collect();
+
+    downmix(&frames, 2)
+}
+
+/// Linearly interpolates mono `samples` from one sample rate to another.
+pub fn resample(samples: &[f32], from: u32, to: u32) -> Vec<f32> {
+    if from == to || samples.is_empty() {
+        return samples.to_vec();
+    }
+
+    let step = from as f64 / to as f64;
+    let len = (samples.len() as f64 / step) as usize;
+
+    (0..len)
+        .map(|index| {
+            let position = index as f64 * step;
+            let before = position as usize;
+            let after = (before + 1).min(samples.len() - 1);
+            let t = (position - before as f64) as f32;
+
+            samples[before] + (samples[after] - samples[before]) * t
+        })
+        .collect()
+}
+
+/// Samples of the window around `time`, clamped to `len` samples.
+fn window(time: f64, window_length: f64, sample_rate: u32, len: usize) -> Range<usize> {
+    let half_length = window_length * 0.5;
+    let sample_rate = sample_rate as f64;
+
+    let end = (((time + half_length) * sample_rate) as usize).min(len);
+    let start = (((time - half_length).max(0.0) * sample_rate) as usize).min(end);
+
+    start..end
+}
+
+/// Runs `rlip_sync` on windows of mono audio.
+pub struct Analyser {
+    lip_sync: LipSync,
+    settings: AnalysisSettings,
+    /// In seconds.
+    last_time: Option<f64>,
+}
+
+impl Analyser {
+    pub fn new(settings: AnalysisSettings) -> Self {
+        Analyser {
+            lip_sync: LipSync::new(),
+            settings,
+            last_time: None,
+        }
+    }
+
+    pub fn settings(&self) -> AnalysisSettings {
+        self.settings
+    }
+
+    /// Estimates the vowel at `time` of `samples`, if an update is due.
+    /// Seeking backwards makes an update due too.
+    pub fn update(&mut self, samples: &[f32], sample_rate: u32, time: f64) -> Option<(Vowel, f32)> {
+        if !self.due(time) {
+            return None;
+        }
+
+        let window = window(
+            time,
+            self.settings.window_length,
+            sample_rate,
+            samples.len(),
+        );
+        self.estimate(&samples[window], sample_rate)
+    }
+
+    /// Estimates the vowel of the end of a stream, `time` being its length, if an update is due.
+    pub fn update_latest(
+        &mut self,
+        samples: &[f32],
+        sample_rate: u32,
+        time: f64,
+    ) -> Option<(Vowel, f32)> {
+        if !self.due(time) {
+            return None;
+        }
+
+        let length = (self.settings.window_length * sample_rate as f64) as usize;
+        self.estimate(
+            &samples[samples.len().saturating_sub(length)..],
+            sample_rate,
+        )
+    }
+
+    fn due(&mut self, time: f64) -> bool {
+        if let Some(last_time) = self.last_time {
+            // Steps of exactly one interval can come out slightly short in f64.
+            if (time - last_time).abs() < 1.0 / self.settings.update_rate - 1e-9 {
+                return false;
+            }
+        }
+        self.last_time = Some(time);
+
+        true
+    }
+
+    fn estimate(&mut self, samples: &[f32], sample_rate: u32) -> Option<(Vowel, f32)> {
+        self.lip_sync
+            .update(resample(samples, sample_rate, ANALYSIS_SAMPLE_RATE));
+        let estimate = self.lip_sync.poll()?;
+
+        Some((Vowel::from_index(estimate.vowel as usize)?, estimate.amount))
+    }
+}
+
+/// The most recent mono samples of a stream.
+pub struct SampleRing {
+    samples: VecDeque<f32>,
+    capacity: usize,
+    sample_rate: u32,
+    channels: usize,
+    /// Samples pushed since the start of the stream.
+    total: u64,
+}
+
+impl SampleRing {
+    /// Keeps `length` seconds of audio of `channels` interleaved channels.
+    pub fn new(sample_rate: u32, channels: usize, length: f64) -> Self {
+        let capacity = (length * sample_rate as f64).ceil() as usize;
+
+        SampleRing {
+            samples: VecDeque::with_capacity(capacity),
+            capacity,
+            sample_rate,
+            channels,
+            total: 0,
+        }
+    }
+
+    /// Pushes interleaved samples, dropping the oldest ones that don't fit anymore.
+    pub fn push(&mut self, chunk: &[f32]) {
+        let mono = downmix(chunk, self.channels);
+        self.total += mono.len() as u64;
+
+        let skip = mono.len().saturating_sub(self.capacity);
+        let overflow = (self.samples.len() + mono.len() - skip).saturating_sub(self.capacity);
+        self.samples.drain(..overflow);
+        self.samples.extend(&mono[skip..]);
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Length of the whole stream, in seconds.
+    pub fn time(&self) -> f64 {
+        self.total as f64 / self.sample_rate as f64
+    }
+
+    pub fn latest(&mut self) -> &[f32] {
+        self.samples.make_contiguous()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct VisemeKey {
+    /// In seconds.
+    pub time: f64,
+    pub vowel: Vowel,
+    pub amount: f32,
+}
+
+/// Mouth shapes of a speech clip, sorted by time.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct VisemeTimeline {
+    pub keys: Vec<VisemeKey>,
+}
+
+impl VisemeTimeline {
+    /// Index of the last key at or before `time`.
+    pub fn key_at(&self, time: f64) -> Option<usize> {
+        self.keys
+            .partition_point(|key| key.time <= time)
+            .checked_sub(1)
+    }
+
+    /// Holds the last key at or before `time`; the mouth is closed before the first one.
+    pub fn weights_at(&self, time: f64) -> [f32; 5] {
+        match self.key_at(time).map(|index| &self.keys[index]) {
+            Some(key) => vowel_weights(key.vowel, key.amount),
+            None => [0.0; 5],
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct BlendSettings {
+    /// Time for a mouth shape to open halfway to its target, in seconds.
+    pub attack: f32,
+    /// Time for a mouth shape to close halfway, in seconds.
+    pub release: f32,
+    /// Share of the previous vowels kept when a new one is estimated, from 0 to 1.
+    pub coarticulation: f32,
+    /// Estimates with a lower amount close the mouth.
+    pub silence_threshold: f32,
+}
+
+impl Default for BlendSettings {
+    fn default() -> Self {
+        BlendSettings {
+            attack: 0.03,
+            release: 0.08,
+            coarticulation: 0.3,
+            silence_threshold: 0.05,
+        }
+    }
+}
+
+/// Turns estimates arriving now and then into mouth shape weights that change every frame.
+#[derive(Clone, Debug, Default)]
+pub struct VisemeBlender {
+    pub settings: BlendSettings,
+    target: [f32; 5],
+    weights: [f32; 5],
+}
+
+impl VisemeBlender {
+    pub fn new(settings: BlendSettings) -> Self {
+        VisemeBlender {
+            settings,
+            ..Default::default()
+        }
+    }
+
+    /// Moves toward `vowel`, keeping some of the previous vowels to blend into it.
+    /// `None` closes the mouth.
+    pub fn set_target(&mut self, vowel: Option<Vowel>, amount: f32) {
+        let vowel = vowel.filter(|_| amount >= self.settings.silence_threshold);
+
+        let Some(vowel) = vowel else {
+            self.target = [0.0; 5];
+            return;
+        };
+
+        let mut target = self
+            .target
+            .map(|weight| weight * self.settings.coarticulation);
+        target[vowel.index()] = target[vowel.index()].max(amount);
+        self.target = target;
+    }
+
+    /// Advances by `delta` seconds and returns the weights.
+    pub fn update(&mut self, delta: f32) -> [f32; 5] {
+        for (weight, target) in self.weights.iter_mut().zip(self.target) {
+            let half_life = if target > *weight {
+                self.settings.attack
+            } else {
+                self.settings.release
+            };
+
+            let t = if half_life > 0.0 {
+                1.0 - 0.5f32.powf(delta / half_life)
+            } else {
+                1.0
+            };
+
+            *weight += (target - *weight) * t;
+        }
+
+        self.weights
+    }
+
+    pub fn weights(&self) -> [f32; 5] {
+        self.weights
+    }
+
+    /// Whether the weights reached the target.
+    pub fn is_settled(&self) -> bool {
+        self.weights
+            .iter()
+            .zip(self.target)
+            .all(|(weight, target)| (weight - target).abs() < 0.001)
+    }
+}
+
+/// Analyses mono `samples` the same way it's done during playback.
+pub fn bake(samples: &[f32], sample_rate: u32, settings: AnalysisSettings) -> VisemeTimeline {
+    let mut analyser = Analyser::new(settings);
+    let mut keys = vec![];
+
+    let duration = samples.len() as f64 / sample_rate as f64;
+    let mut step = 0;
+
+    loop {
+        let time = step as f64 / settings.update_rate;
+        if time > duration {
+            break;
+        }
+        step += 1;
+
+        if let Some((vowel, amount)) = analyser.update(samples, sample_rate, time) {
+            keys.push(VisemeKey {
+                time,
+                vowel,
+                amount,
+            });
+        }
+    }
+
+    VisemeTimeline { keys }
+}
+
+#[test]
+fn test_timeline_holds_last_key() {
+    let timeline = VisemeTimeline {
+        keys: vec![
+            VisemeKey {
+                time: 0.5,
+                vowel: Vowel::A,
+                amount: 0.8,
+            },
+            VisemeKey {
+                time: 1.0,
+                vowel: Vowel::O,
+                amount: 0.4,
+            },
+        ],
+    };
+
+    assert_eq!(timeline.weights_at(0.0), [0.0; 5]);
+    assert_eq!(timeline.weights_at(0.7), [0.8, 0.0, 0.0, 0.0, 0.0]);
+    assert_eq!(timeline.weights_at(1.0), [0.0, 0.0, 0.0, 0.0, 0.4]);
+}
+
+#[test]
+fn test_downmix_and_resample() {
+    assert_eq!(downmix(&[1.0, 0.0, 0.5, 0.5], 2), vec![0.5, 0.5]);
+    assert_eq!(
+        resample(&[0.0, 1.0, 2.0, 3.0], 2, 4),
+        vec![0.0, 0.5, 1.0, 1.5, 2.0, 2.5, 3.0, 3.0]
+    );
+    assert_eq!(resample(&[0.0, 1.0, 2.0, 3.0], 4, 2), vec![0.0, 2.0]);
+}
+
+#[test]
+fn test_blender_coarticulation_and_silence() {
+    let mut blender = VisemeBlender::new(BlendSettings {
+        attack: 0.0,
+        ..Default::default()
+    });
+
+    blender.set_target(Some(Vowel::A), 1.0);
+    assert_eq!(blender.update(0.01), [1.0, 0.0, 0.0, 0.0, 0.0]);
+
+    blender.set_target(Some(Vowel::O), 0.5);
+    let weights = blender.update(0.08);
+    assert!((weights[0] - 0.65).abs() < 0.001);
+    assert_eq!(weights[4], 0.5);
+
+    blender.set_target(Some(Vowel::I), 0.01);
+    assert!(blender.update(0.08).iter().all(|weight| *weight < 0.5));
+}
+
+#[test]
+fn test_sample_ring_keeps_latest() {
+    let mut ring = SampleRing::new(4, 2, 1.0);
+
+    ring.push(&[1.0, 1.0, 2.0, 2.0, 3.0, 3.0]);
+    ring.push(&[4.0, 4.0, 5.0, 5.0]);
+    assert_eq!(ring.latest(), &[2.0, 3.0, 4.0, 5.0]);
+
+    ring.push(&[0.0; 12]);
+    assert_eq!(ring.latest(), &[0.0; 4]);
+    assert_eq!(ring.time(), 2.75);
+}
+
+/// Runs the analysis and blending over the fixtures in `assets/sounds` and compares the results
+/// with `tests/golden/<name>.json`. Run with `UPDATE_GOLDEN=1` to rewrite them after an intended change.
+#[test]
+fn test_golden_visemes() {
+    use kira::sound::static_sound::StaticSoundSettings;
+    use std::path::Path;
+
+    /// Blended weights are sampled at this rate, in Hz.
+    const FRAME_RATE: f64 = 60.0;
+    const TOLERANCE: f32 = 1e-4;
+
+    #[derive(Serialize, Deserialize)]
+    struct Golden {
+        keys: Vec<VisemeKey>,
+        blended: Vec<[f32; 5]>,
+    }
+
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+
+    for name in ["sound"] {
+        let sound_data = StaticSoundData::from_file(
+            root.join("assets/sounds").join(format!("{}.ogg", name)),
+            StaticSoundSettings::default(),
+        )
+        .unwrap();
+
+        let samples = mono_samples(&sound_data);
+
+        let timeline = bake(
+            &samples,
+            sound_data.sample_rate,
+            AnalysisSettings::default(),
+        );
+
+        // Feed the keys to a blender the way playback does.
+        let mut blender = VisemeBlender::default();
+        let mut key = None;
+        let mut blended = vec![];
+        let duration = samples.len() as f64 / sound_data.sample_rate as f64;
+
+        for frame in 0..(duration * FRAME_RATE) as usize {
+            let current_key = timeline.key_at(frame as f64 / FRAME_RATE);
+            if current_key != key {
+                key = current_key;
+                match current_key.map(|index| &timeline.keys[index]) {
+                    Some(k) => blender.set_target(Some(k.vowel), k.amount),
+                    None => blender.set_target(None, 0.0),
+                }
+            }
+            blended.push(blender.update((1.0 / FRAME_RATE) as f32));
+        }
+
+        let actual = Golden {
+            keys: timeline.keys,
+            blended,
+        };
+
+        let path = root.join("tests/golden").join(format!("{}.json", name));
+
+        if update {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, serde_json::to_string_pretty(&actual).unwrap()).unwrap();
+            continue;
+        }
+
+        let Ok(golden) = std::fs::read(&path) else {
+            panic!("Missing {:?}, run with UPDATE_GOLDEN=1 to create it", path);
+        };
+        let golden: Golden = serde_json::from_slice(&golden).unwrap();
+
+        assert_eq!(actual.keys.len(), golden.keys.len(), "{}: key count", name);
+        for (actual, golden) in actual.keys.iter().zip(&golden.keys) {
+            assert!(
+                actual.time == golden.time
+                    && actual.vowel == golden.vowel
+                    && (actual.amount - golden.amount).abs() < TOLERANCE,
+                "{}: key {:?} should be {:?}",
+                name,
+                actual,
+                golden
+            );
+        }
+
+        assert_eq!(
+            actual.blended.len(),
+            golden.blended.len(),
+            "{}: frame count",
+            name
+        );
+        for (frame, (actual, golden)) in actual.blended.iter().zip(&golden.blended).enumerate() {
+            let matches = actual
+                .iter()
+                .zip(golden)
+                .all(|(actual, golden)| (actual - golden).abs() < TOLERANCE);
+            assert!(
+                matches,
+                "{}: frame {} is {:?}, should be {:?}",
+                name, frame, actual, golden
+            );
+        }
+    }
+}
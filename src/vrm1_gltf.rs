@@ -0,0 +1,295 @@
+//! Types of the VRM 1.0 extensions: `VRMC_vrm`, `VRMC_springBone` and `VRMC_materials_mtoon`.
+//!
+//! Unlike [`crate::vrm_gltf`] these are only used to write models, so they're plain serde types.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct VrmcVrm {
+    pub spec_version: String,
+    pub meta: Meta,
+    pub humanoid: Humanoid,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_person: Option<FirstPerson>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub look_at: Option<LookAt>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expressions: Option<Expressions>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Meta {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    pub authors: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contact_information: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub references: Vec<String>,
+    /// Index of an image, not of a texture as in VRM 0.x.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thumbnail_image: Option<u32>,
+    pub license_url: String,
+    pub avatar_permission: AvatarPermission,
+    pub allow_excessively_violent_usage: bool,
+    pub allow_excessively_sexual_usage: bool,
+    pub commercial_usage: CommercialUsage,
+    pub allow_political_or_religious_usage: bool,
+    pub allow_antisocial_or_hate_usage: bool,
+    pub credit_notation: CreditNotation,
+    pub allow_redistribution: bool,
+    pub modification: Modification,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub other_license_url: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum AvatarPermission {
+    OnlyAuthor,
+    OnlySeparatelyLicensedPerson,
+    Everyone,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum CommercialUsage {
+    PersonalNonProfit,
+    PersonalProfit,
+    Corporation,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum CreditNotation {
+    Required,
+    Unnecessary,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Modification {
+    Prohibited,
+    AllowModification,
+    AllowModificationRedistribution,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Humanoid {
+    pub human_bones: BTreeMap<String, HumanBone>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HumanBone {
+    pub node: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct FirstPerson {
+    pub mesh_annotations: Vec<MeshAnnotation>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MeshAnnotation {
+    pub node: u32,
+    #[serde(rename = "type")]
+    pub kind: FirstPersonType,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum FirstPersonType {
+    Auto,
+    Both,
+    ThirdPersonOnly,
+    FirstPersonOnly,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct LookAt {
+    pub offset_from_head_bone: [f32; 3],
+    #[serde(rename = "type")]
+    pub kind: LookAtType,
+    pub range_map_horizontal_inner: RangeMap,
+    pub range_map_horizontal_outer: RangeMap,
+    pub range_map_vertical_down: RangeMap,
+    pub range_map_vertical_up: RangeMap,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum LookAtType {
+    Bone,
+    Expression,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RangeMap {
+    /// In degrees.
+    pub input_max_value: f32,
+    pub output_scale: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Expressions {
+    pub preset: BTreeMap<String, Expression>,
+    pub custom: BTreeMap<String, Expression>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Expression {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub morph_target_binds: Vec<MorphTargetBind>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub material_color_binds: Vec<MaterialColorBind>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub texture_transform_binds: Vec<TextureTransformBind>,
+    pub is_binary: bool,
+    pub override_blink: ExpressionOverride,
+    pub override_look_at: ExpressionOverride,
+    pub override_mouth: ExpressionOverride,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MorphTargetBind {
+    pub node: u32,
+    pub index: u32,
+    /// In the range of 0 to 1.
+    pub weight: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct MaterialColorBind {
+    pub material: u32,
+    #[serde(rename = "type")]
+    pub kind: MaterialColorType,
+    pub target_value: [f32; 4],
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum MaterialColorType {
+    Color,
+    EmissionColor,
+    ShadeColor,
+    MatcapColor,
+    RimColor,
+    OutlineColor,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TextureTransformBind {
+    pub material: u32,
+    pub scale: [f32; 2],
+    pub offset: [f32; 2],
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum ExpressionOverride {
+    #[default]
+    None,
+    Block,
+    Blend,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct VrmcSpringBone {
+    pub spec_version: String,
+    pub colliders: Vec<SpringCollider>,
+    pub collider_groups: Vec<SpringColliderGroup>,
+    pub springs: Vec<Spring>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SpringCollider {
+    pub node: u32,
+    pub shape: ColliderShape,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ColliderShape {
+    pub sphere: Sphere,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Sphere {
+    pub offset: [f32; 3],
+    pub radius: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SpringColliderGroup {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub colliders: Vec<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Spring {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub joints: Vec<SpringJoint>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub collider_groups: Vec<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub center: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SpringJoint {
+    pub node: u32,
+    pub hit_radius: f32,
+    pub stiffness: f32,
+    pub gravity_power: f32,
+    pub gravity_dir: [f32; 3],
+    pub drag_force: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct VrmcMaterialsMtoon {
+    pub spec_version: String,
+    pub transparent_with_z_write: bool,
+    pub render_queue_offset_number: i32,
+    pub shade_color_factor: [f32; 3],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shade_multiply_texture: Option<TextureInfo>,
+    pub shading_shift_factor: f32,
+    pub shading_toony_factor: f32,
+    pub gi_equalization_factor: f32,
+    pub matcap_factor: [f32; 3],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub matcap_texture: Option<TextureInfo>,
+    pub outline_width_mode: OutlineWidthMode,
+    /// In meters for world coordinates, relative to the screen height otherwise.
+    pub outline_width_factor: f32,
+    pub outline_color_factor: [f32; 3],
+    pub outline_lighting_mix_factor: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TextureInfo {
+    pub index: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum OutlineWidthMode {
+    None,
+    WorldCoordinates,
+    ScreenCoordinates,
+}
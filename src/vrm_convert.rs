@@ -0,0 +1,911 @@
+//! Migrates VRM 0.x models to VRM 1.0.
+//!
+//! Besides translating the `VRM` extension into `VRMC_vrm`, `VRMC_springBone` and
+//! `VRMC_materials_mtoon`, the model itself has to turn around: VRM 0.x models face -Z,
+//! VRM 1.0 ones face +Z. Every node, vertex, skin and animation is rotated 180 degrees around Y.
+
+use crate::glb::{push, GlbFile};
+use crate::vrm1_gltf as vrm1;
+use crate::vrm_gltf::{self as vrm0, read_vrm, VrmError};
+use serde_json::{json, Value};
+use std::collections::{BTreeSet, HashSet};
+
+const LICENSE_URL: &str = "https://vrm.dev/licenses/1.0/";
+
+/// Reads a VRM 0.x `.vrm` file and returns the bytes of the equivalent VRM 1.0 file.
+pub fn convert_vrm0_to_vrm1(bytes: &[u8]) -> Result<Vec<u8>, VrmError> {
+    let vrm = read_vrm(bytes)?;
+    let mut glb = GlbFile::from_slice(bytes)?;
+
+    rotate_model(&mut glb)?;
+
+    let vrmc_vrm = vrm1::VrmcVrm {
+        spec_version: "1.0".to_string(),
+        meta: convert_meta(&vrm.meta, &glb.json),
+        humanoid: convert_humanoid(&vrm.humanoid),
+        first_person: Some(convert_first_person(&vrm.first_person, &glb.json)),
+        look_at: Some(convert_look_at(&vrm.first_person)),
+        expressions: Some(convert_expressions(
+            &vrm.blend_shape_master,
+            &vrm.material_properties,
+            &glb.json,
+        )),
+    };
+    let spring_bone = convert_spring_bones(&vrm.secondary_animation, &mut glb.json);
+
+    let mut used = vec!["VRMC_vrm", "VRMC_springBone"];
+
+    for (index, property) in vrm.material_properties.iter().enumerate() {
+        if property.shader != "VRM/MToon" {
+            continue;
+        }
+        let Some(material) = material_index(&glb.json, property, index) else {
+            continue;
+        };
+
+        let mtoon = serde_json::to_value(convert_mtoon(property)).map_err(VrmError::Json)?;
+        let Some(material) = glb.json["materials"]
+            .get_mut(material as usize)
+            .and_then(Value::as_object_mut)
+        else {
+            continue;
+        };
+        let extensions = material
+            .entry("extensions")
+            .or_insert_with(|| Value::Object(Default::default()));
+        if !extensions.is_object() {
+            *extensions = Value::Object(Default::default());
+        }
+        extensions["VRMC_materials_mtoon"] = mtoon;
+
+        if !used.contains(&"VRMC_materials_mtoon") {
+            used.push("VRMC_materials_mtoon");
+        }
+    }
+
+    let json = &mut glb.json;
+
+    let extensions = json["extensions"]
+        .as_object_mut()
+        .ok_or(VrmError::MissingExtension)?;
+    extensions.remove("VRM");
+    extensions.insert(
+        "VRMC_vrm".to_string(),
+        serde_json::to_value(vrmc_vrm).map_err(VrmError::Json)?,
+    );
+    extensions.insert(
+        "VRMC_springBone".to_string(),
+        serde_json::to_value(spring_bone).map_err(VrmError::Json)?,
+    );
+
+    let mut extensions_used: Vec<Value> = json["extensionsUsed"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|name| name != "VRM")
+        .collect();
+    extensions_used.extend(used.into_iter().map(Value::from));
+    json["extensionsUsed"] = Value::Array(extensions_used);
+
+    glb.to_vec()
+}
+
+/// Rotation of a vector by 180 degrees around Y.
+fn turn_around(v: &mut [f32]) {
+    v[0] = -v[0];
+    v[2] = -v[2];
+}
+
+/// VRM 0.x stores collider offsets, gravity directions and the first person offset in Unity's
+/// left-handed space, which UniVRM exports to glTF by flipping Z. Flip Z, then turn around.
+fn unity_to_vrm1(v: &vrm0::Vec3) -> [f32; 3] {
+    [-v.x, v.y, v.z]
+}
+
+/// VRM 0.x colors are in sRGB like Unity's, VRM 1.0 colors are linear like glTF's.
+fn srgb_to_linear(component: f32) -> f32 {
+    if component <= 0.04045 {
+        component / 12.92
+    } else {
+        ((component + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Alpha stays as it is, it's linear in both.
+fn color_to_linear(color: [f32; 4]) -> [f32; 4] {
+    [
+        srgb_to_linear(color[0]),
+        srgb_to_linear(color[1]),
+        srgb_to_linear(color[2]),
+        color[3],
+    ]
+}
+
+/// Fails if an accessor that has to turn around isn't floats, rather than leaving it as is.
+fn rotate_model(glb: &mut GlbFile) -> Result<(), VrmError> {
+    // Vectors and quaternions alike only need X and Z negated.
+    let mut vectors = BTreeSet::new();
+    let mut matrices = BTreeSet::new();
+
+    let accessor = |value: &Value| value.as_u64().map(|index| index as usize);
+
+    for mesh in glb.json["meshes"].as_array().into_iter().flatten() {
+        for primitive in mesh["primitives"].as_array().into_iter().flatten() {
+            for attribute in ["POSITION", "NORMAL", "TANGENT"] {
+                vectors.extend(accessor(&primitive["attributes"][attribute]));
+
+                for target in primitive["targets"].as_array().into_iter().flatten() {
+                    vectors.extend(accessor(&target[attribute]));
+                }
+            }
+        }
+    }
+
+    for skin in glb.json["skins"].as_array().into_iter().flatten() {
+        matrices.extend(accessor(&skin["inverseBindMatrices"]));
+    }
+
+    for animation in glb.json["animations"].as_array().into_iter().flatten() {
+        for channel in animation["channels"].as_array().into_iter().flatten() {
+            if !matches!(
+                channel["target"]["path"].as_str(),
+                Some("translation" | "rotation")
+            ) {
+                continue;
+            }
+            let Some(sampler) = accessor(&channel["sampler"]) else {
+                continue;
+            };
+            vectors.extend(accessor(&animation["samplers"][sampler]["output"]));
+        }
+    }
+
+    // Morph targets often only store the vertices they move.
+    let layout = |glb: &mut GlbFile, index: usize| {
+        if glb.json["accessors"][index].get("sparse").is_some() {
+            glb.densify_accessor(index);
+        }
+        glb.float_accessor(index)
+            .ok_or(VrmError::UnsupportedAccessor { index })
+    };
+
+    for index in vectors {
+        let layout = layout(glb, index)?;
+        glb.map_floats(layout, turn_around);
+
+        // The bounds of positions are mandatory, so keep them right.
+        let Some(bounds) = glb
+            .json
+            .get_mut("accessors")
+            .and_then(|accessors| accessors.get_mut(index))
+        else {
+            continue;
+        };
+        let (Some(min), Some(max)) = (
+            bounds.get("min").and_then(Value::as_array).cloned(),
+            bounds.get("max").and_then(Value::as_array).cloned(),
+        ) else {
+            continue;
+        };
+        for component in [0, 2] {
+            if let (Some(low), Some(high)) = (
+                min.get(component).and_then(Value::as_f64),
+                max.get(component).and_then(Value::as_f64),
+            ) {
+                bounds["min"][component] = (-high).into();
+                bounds["max"][component] = (-low).into();
+            }
+        }
+    }
+
+    for index in matrices {
+        let layout = layout(glb, index)?;
+        glb.map_floats(layout, turn_matrix_around);
+    }
+
+    let nodes = glb.json.get_mut("nodes").and_then(Value::as_array_mut);
+    for node in nodes.into_iter().flatten() {
+        for property in ["translation", "rotation"] {
+            if let Some(values) = node.get_mut(property).and_then(Value::as_array_mut) {
+                for component in [0, 2] {
+                    if let Some(value) = values.get_mut(component) {
+                        if let Some(float) = value.as_f64() {
+                            *value = (-float).into();
+                        }
+                    }
+                }
+            }
+        }
+
+        if let Some(values) = node.get_mut("matrix") {
+            let mut matrix: Vec<f32> = values
+                .as_array()
+                .into_iter()
+                .flatten()
+                .map(|value| value.as_f64().unwrap_or(0.0) as f32)
+                .collect();
+            if matrix.len() == 16 {
+                turn_matrix_around(&mut matrix);
+                *values = matrix.into();
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// `R * M * R` for the 180 degree rotation `R`, on a column-major matrix.
+fn turn_matrix_around(matrix: &mut [f32]) {
+    let flipped = |i: usize| i == 0 || i == 2;
+
+    for column in 0..4 {
+        for row in 0..4 {
+            if flipped(row) != flipped(column) {
+                matrix[column * 4 + row] = -matrix[column * 4 + row];
+            }
+        }
+    }
+}
+
+fn convert_meta(meta: &vrm0::Meta, json: &Value) -> vrm1::Meta {
+    let non_empty = |s: &String| (!s.is_empty()).then(|| s.clone());
+
+    let thumbnail_image = meta
+        .texture
+        .and_then(|texture| json["textures"][texture as usize]["source"].as_u64())
+        .map(|image| image as u32);
+
+    let avatar_permission = match meta.allowed_user() {
+        vrm0::AllowedUser::OnlyAuthor => vrm1::AvatarPermission::OnlyAuthor,
+        vrm0::AllowedUser::ExplicitlyLicensedPerson => {
+            vrm1::AvatarPermission::OnlySeparatelyLicensedPerson
+        }
+        vrm0::AllowedUser::Everyone => vrm1::AvatarPermission::Everyone,
+    };
+
+    let mut commercial_usage = match meta.commercial_usage() {
+        vrm0::Usage::Allow => vrm1::CommercialUsage::PersonalProfit,
+        vrm0::Usage::Disallow => vrm1::CommercialUsage::PersonalNonProfit,
+    };
+
+    // VRM 0.x only names a license, VRM 1.0 spells out what it allows.
+    let license = meta.license_name.as_str();
+    let (allow_redistribution, credit_notation, modification) = match license {
+        "CC0" => (
+            true,
+            vrm1::CreditNotation::Unnecessary,
+            vrm1::Modification::AllowModificationRedistribution,
+        ),
+        "CC_BY" | "CC_BY_SA" | "CC_BY_NC" | "CC_BY_NC_SA" => (
+            true,
+            vrm1::CreditNotation::Required,
+            vrm1::Modification::AllowModificationRedistribution,
+        ),
+        "CC_BY_ND" | "CC_BY_NC_ND" => (
+            true,
+            vrm1::CreditNotation::Required,
+            vrm1::Modification::Prohibited,
+        ),
+        _ => (
+            false,
+            vrm1::CreditNotation::Required,
+            vrm1::Modification::Prohibited,
+        ),
+    };
+    if license.contains("_NC") {
+        commercial_usage = vrm1::CommercialUsage::PersonalNonProfit;
+    }
+
+    let mut other_license_url = non_empty(&meta.other_license_url);
+    if other_license_url.is_none() {
+        other_license_url = non_empty(&meta.other_permission_url);
+    }
+
+    vrm1::Meta {
+        name: meta.title.clone(),
+        version: non_empty(&meta.version),
+        authors: vec![non_empty(&meta.author).unwrap_or_else(|| "Unknown".to_string())],
+        contact_information: non_empty(&meta.contact_information),
+        references: non_empty(&meta.reference).into_iter().collect(),
+        thumbnail_image,
+        license_url: LICENSE_URL.to_string(),
+        avatar_permission,
+        allow_excessively_violent_usage: meta.violent_usage() == vrm0::Usage::Allow,
+        allow_excessively_sexual_usage: meta.sexual_usage() == vrm0::Usage::Allow,
+        commercial_usage,
+        allow_political_or_religious_usage: false,
+        allow_antisocial_or_hate_usage: false,
+        credit_notation,
+        allow_redistribution,
+        modification,
+        other_license_url,
+    }
+}
+
+fn convert_humanoid(humanoid: &vrm0::Humanoid) -> vrm1::Humanoid {
+    let human_bones = humanoid
+        .human_bones
+        .iter()
+        .map(|bone| {
+            // VRM 1.0 names the thumb bones like the other fingers, one joint closer to the hand.
+            let name = match bone.name.as_str() {
+                "leftThumbProximal" => "leftThumbMetacarpal",
+                "leftThumbIntermediate" => "leftThumbProximal",
+                "rightThumbProximal" => "rightThumbMetacarpal",
+                "rightThumbIntermediate" => "rightThumbProximal",
+                name => name,
+            };
+            (name.to_string(), vrm1::HumanBone { node: bone.node })
+        })
+        .collect();
+
+    vrm1::Humanoid { human_bones }
+}
+
+/// VRM 1.0 refers to nodes where VRM 0.x refers to meshes.
+fn nodes_with_mesh(json: &Value, mesh: u32) -> Vec<u32> {
+    json["nodes"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .enumerate()
+        .filter(|(_, node)| node["mesh"].as_u64() == Some(mesh as u64))
+        .map(|(index, _)| index as u32)
+        .collect()
+}
+
+fn convert_first_person(first_person: &vrm0::FirstPerson, json: &Value) -> vrm1::FirstPerson {
+    let mut mesh_annotations = vec![];
+
+    for annotation in &first_person.mesh_annotations {
+        let kind = match annotation.first_person_flag.as_str() {
+            "Both" => vrm1::FirstPersonType::Both,
+            "ThirdPersonOnly" => vrm1::FirstPersonType::ThirdPersonOnly,
+            "FirstPersonOnly" => vrm1::FirstPersonType::FirstPersonOnly,
+            _ => vrm1::FirstPersonType::Auto,
+        };
+
+        for node in nodes_with_mesh(json, annotation.mesh) {
+            mesh_annotations.push(vrm1::MeshAnnotation { node, kind });
+        }
+    }
+
+    vrm1::FirstPerson { mesh_annotations }
+}
+
+fn convert_look_at(first_person: &vrm0::FirstPerson) -> vrm1::LookAt {
+    let range_map = |curve: &vrm0::LookAtCurve| vrm1::RangeMap {
+        input_max_value: curve.x_range,
+        output_scale: curve.y_range,
+    };
+
+    vrm1::LookAt {
+        offset_from_head_bone: unity_to_vrm1(&first_person.first_person_bone_offset),
+        kind: match first_person.look_at_type_name.as_str() {
+            "BlendShape" => vrm1::LookAtType::Expression,
+            _ => vrm1::LookAtType::Bone,
+        },
+        range_map_horizontal_inner: range_map(&first_person.look_at_horizontal_inner),
+        range_map_horizontal_outer: range_map(&first_person.look_at_horizontal_outer),
+        range_map_vertical_down: range_map(&first_person.look_at_vertical_down),
+        range_map_vertical_up: range_map(&first_person.look_at_vertical_up),
+    }
+}
+
+fn material_index(json: &Value, property: &vrm0::MaterialProperty, fallback: usize) -> Option<u32> {
+    let materials = json["materials"].as_array()?;
+
+    materials
+        .iter()
+        .position(|material| material["name"].as_str() == Some(property.name.as_str()))
+        .or((fallback < materials.len()).then_some(fallback))
+        .map(|index| index as u32)
+}
+
+fn convert_expressions(
+    master: &vrm0::BlendShapeMaster,
+    material_properties: &[vrm0::MaterialProperty],
+    json: &Value,
+) -> vrm1::Expressions {
+    let mut expressions = vrm1::Expressions::default();
+
+    for group in &master.blend_shape_groups {
+        let mut expression = vrm1::Expression {
+            is_binary: group.is_binary,
+            ..Default::default()
+        };
+
+        for bind in &group.binds {
+            for node in nodes_with_mesh(json, bind.mesh) {
+                expression.morph_target_binds.push(vrm1::MorphTargetBind {
+                    node,
+                    index: bind.index,
+                    weight: (bind.weight / 100.0).clamp(0.0, 1.0),
+                });
+            }
+        }
+
+        for value in &group.material_values {
+            let Some(material) = material_properties
+                .iter()
+                .enumerate()
+                .find(|(_, property)| property.name == value.material_name)
+                .and_then(|(index, property)| material_index(json, property, index))
+            else {
+                continue;
+            };
+
+            let target = &value.target_value;
+            let kind = match value.property_name.as_str() {
+                "_Color" => vrm1::MaterialColorType::Color,
+                "_EmissionColor" => vrm1::MaterialColorType::EmissionColor,
+                "_ShadeColor" => vrm1::MaterialColorType::ShadeColor,
+                "_RimColor" => vrm1::MaterialColorType::RimColor,
+                "_OutlineColor" => vrm1::MaterialColorType::OutlineColor,
+                "_MainTex_ST" if target.len() == 4 => {
+                    // Unity's UV origin is at the bottom, glTF's at the top.
+                    expression
+                        .texture_transform_binds
+                        .push(vrm1::TextureTransformBind {
+                            material,
+                            scale: [target[0], target[1]],
+                            offset: [target[2], 1.0 - target[3] - target[1]],
+                        });
+                    continue;
+                }
+                _ => continue,
+            };
+
+            if target.len() == 4 {
+                expression
+                    .material_color_binds
+                    .push(vrm1::MaterialColorBind {
+                        material,
+                        kind,
+                        target_value: color_to_linear([target[0], target[1], target[2], target[3]]),
+                    });
+            }
+        }
+
+        let preset = match group.preset_name.as_str() {
+            "neutral" => Some("neutral"),
+            "a" => Some("aa"),
+            "i" => Some("ih"),
+            "u" => Some("ou"),
+            "e" => Some("ee"),
+            "o" => Some("oh"),
+            "blink" => Some("blink"),
+            "blink_l" => Some("blinkLeft"),
+            "blink_r" => Some("blinkRight"),
+            "joy" => Some("happy"),
+            "angry" => Some("angry"),
+            "sorrow" => Some("sad"),
+            "fun" => Some("relaxed"),
+            "lookup" => Some("lookUp"),
+            "lookdown" => Some("lookDown"),
+            "lookleft" => Some("lookLeft"),
+            "lookright" => Some("lookRight"),
+            _ => None,
+        };
+
+        match preset {
+            Some(preset) => {
+                expressions.preset.insert(preset.to_string(), expression);
+            }
+            None => {
+                expressions.custom.insert(group.name.clone(), expression);
+            }
+        }
+    }
+
+    expressions
+}
+
+fn convert_spring_bones(
+    secondary_animation: &vrm0::SecondaryAnimation,
+    json: &mut Value,
+) -> vrm1::VrmcSpringBone {
+    let mut spring_bone = vrm1::VrmcSpringBone {
+        spec_version: "1.0".to_string(),
+        ..Default::default()
+    };
+
+    for group in &secondary_animation.collider_groups {
+        let mut colliders = vec![];
+
+        for collider in &group.colliders {
+            colliders.push(spring_bone.colliders.len() as u32);
+            spring_bone.colliders.push(vrm1::SpringCollider {
+                node: group.node,
+                shape: vrm1::ColliderShape {
+                    sphere: vrm1::Sphere {
+                        offset: unity_to_vrm1(&collider.offset),
+                        radius: collider.radius,
+                    },
+                },
+            });
+        }
+
+        spring_bone.collider_groups.push(vrm1::SpringColliderGroup {
+            name: None,
+            colliders,
+        });
+    }
+
+    // Joints already in a spring, as VRM 0.x root bones may overlap.
+    let mut seen = HashSet::new();
+
+    for group in &secondary_animation.bone_groups {
+        let joint = |node: u32| vrm1::SpringJoint {
+            node,
+            hit_radius: group.hit_radius,
+            stiffness: group.stiffiness,
+            gravity_power: group.gravity_power,
+            gravity_dir: unity_to_vrm1(&group.gravity_dir),
+            drag_force: group.drag_force,
+        };
+
+        // VRM 0.x lists root bones and simulates everything below them,
+        // VRM 1.0 wants every branch as its own chain of joints.
+        for root in &group.bones {
+            for mut chain in chains(json, *root, &mut seen) {
+                // VRM 1.0 only swings a joint toward the next one, VRM 0.x swings the last one
+                // toward a virtual tail.
+                let last = *chain.last().unwrap();
+                if chain.len() < 2 || is_leaf(json, last) {
+                    chain.extend(add_tail(json, last));
+                }
+
+                spring_bone.springs.push(vrm1::Spring {
+                    name: (!group.comment.is_empty()).then(|| group.comment.clone()),
+                    joints: chain.into_iter().map(joint).collect(),
+                    collider_groups: group.collider_groups.clone(),
+                    center: group.center,
+                });
+            }
+        }
+    }
+
+    spring_bone
+}
+
+/// Splits the tree below `root` into chains with each node in one chain only, so no joint is
+/// simulated twice. A chain follows the first child of each node, the other children start
+/// chains of their own. Nodes in `seen` are left out, and added to it, as are missing nodes.
+fn chains(json: &Value, root: u32, seen: &mut HashSet<u32>) -> Vec<Vec<u32>> {
+    let mut chains = vec![];
+    let mut starts = vec![root];
+
+    while let Some(start) = starts.pop() {
+        let mut chain = vec![];
+        let mut next = Some(start);
+
+        while let Some(node) = next {
+            if json["nodes"].get(node as usize).is_none() {
+                println!("Spring bone refers to missing node {}, left out", node);
+                break;
+            }
+            if !seen.insert(node) {
+                break;
+            }
+            chain.push(node);
+
+            let mut children = json["nodes"][node as usize]["children"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(Value::as_u64)
+                .map(|child| child as u32);
+            next = children.next();
+            // Popped in order once this chain ends.
+            let branches: Vec<u32> = children.collect();
+            starts.extend(branches.into_iter().rev());
+        }
+
+        if !chain.is_empty() {
+            chains.push(chain);
+        }
+    }
+
+    chains
+}
+
+fn is_leaf(json: &Value, node: u32) -> bool {
+    json["nodes"][node as usize]["children"]
+        .as_array()
+        .map_or(0, Vec::len)
+        == 0
+}
+
+/// Length of the virtual tail UniVRM 0.x simulates leaf bones with, in meters.
+const TAIL_LENGTH: f32 = 0.07;
+
+/// Adds a child to `node` where UniVRM 0.x puts the virtual tail of a leaf bone: further along
+/// the direction from its parent, and returns its index. Returns `None` if `node` is missing.
+fn add_tail(json: &mut Value, node: u32) -> Option<u32> {
+    let Some(joint) = json["nodes"].get(node as usize) else {
+        println!("Spring bone refers to missing node {}, no tail added", node);
+        return None;
+    };
+    let component = |property: &str, index: usize, default: f32| {
+        joint[property][index]
+            .as_f64()
+            .map_or(default, |value| value as f32)
+    };
+    let translation = [0, 1, 2].map(|index| component("translation", index, 0.0));
+    let rotation =
+        [0, 1, 2, 3].map(|index| component("rotation", index, [0.0, 0.0, 0.0, 1.0][index]));
+
+    // The direction from the parent is in the parent's space, the tail goes in the node's.
+    let direction = rotate_inverse(rotation, translation);
+    let length = direction.iter().map(|v| v * v).sum::<f32>().sqrt();
+    let translation = if length > f32::EPSILON {
+        direction.map(|v| v / length * TAIL_LENGTH)
+    } else {
+        [0.0, TAIL_LENGTH, 0.0]
+    };
+
+    let name = format!("{}_tail", joint["name"].as_str().unwrap_or("joint"));
+    let tail = push(
+        json,
+        "nodes",
+        json!({ "name": name, "translation": translation }),
+    ) as u32;
+
+    let node = json
+        .get_mut("nodes")
+        .and_then(|nodes| nodes.get_mut(node as usize))
+        .and_then(Value::as_object_mut)?;
+    let children = node.entry("children").or_insert_with(|| json!([]));
+    if !children.is_array() {
+        *children = json!([]);
+    }
+    children.as_array_mut()?.push(tail.into());
+
+    Some(tail)
+}
+
+/// Rotates `v` by the inverse of the unit quaternion `q`.
+fn rotate_inverse(q: [f32; 4], v: [f32; 3]) -> [f32; 3] {
+    let cross = |a: [f32; 3], b: [f32; 3]| {
+        [
+            a[1] * b[2] - a[2] * b[1],
+            a[2] * b[0] - a[0] * b[2],
+            a[0] * b[1] - a[1] * b[0],
+        ]
+    };
+    let axis = [-q[0], -q[1], -q[2]];
+    let t = cross(axis, v).map(|c| c * 2.0);
+    let u = cross(axis, t);
+
+    [0, 1, 2].map(|i| v[i] + q[3] * t[i] + u[i])
+}
+
+fn convert_mtoon(property: &vrm0::MaterialProperty) -> vrm1::VrmcMaterialsMtoon {
+    let float = &property.float;
+    let vector = &property.vector;
+    let keywords = &property.keyword_map;
+
+    // MToon 0.x describes the shading ramp by where it starts and how toony it is,
+    // MToon 1.0 by its center and width.
+    let range_min = float.shade_shift;
+    let range_max = 1.0 + (float.shade_shift - 1.0) * float.shade_toony;
+    let shading_toony_factor = ((2.0 - (range_max - range_min)) * 0.5).clamp(0.0, 1.0);
+    let shading_shift_factor = (-(range_max + range_min) * 0.5).clamp(-1.0, 1.0);
+
+    let outline_width_mode = if keywords.outline_width_world == Some(true) {
+        vrm1::OutlineWidthMode::WorldCoordinates
+    } else if keywords.outline_width_screen == Some(true) {
+        vrm1::OutlineWidthMode::ScreenCoordinates
+    } else {
+        vrm1::OutlineWidthMode::None
+    };
+
+    let render_queue_offset_number = match property.tag_map.render_type {
+        vrm0::RenderType::Transparent => (property.render_queue - 3000).clamp(-9, 9),
+        _ => 0,
+    };
+
+    let rgb = |color: &[f32; 4]| {
+        let color = color_to_linear(*color);
+        [color[0], color[1], color[2]]
+    };
+
+    vrm1::VrmcMaterialsMtoon {
+        spec_version: "1.0".to_string(),
+        transparent_with_z_write: false,
+        render_queue_offset_number,
+        shade_color_factor: rgb(&vector.shade_color),
+        shade_multiply_texture: property
+            .texture
+            .shade_texture
+            .map(|index| vrm1::TextureInfo { index }),
+        shading_shift_factor,
+        shading_toony_factor,
+        gi_equalization_factor: (1.0 - float.indirect_light_insensity).clamp(0.0, 1.0),
+        matcap_factor: [1.0, 1.0, 1.0],
+        matcap_texture: property
+            .texture
+            .sphere_add
+            .map(|index| vrm1::TextureInfo { index }),
+        outline_width_mode,
+        // Centimeters in VRM 0.x.
+        outline_width_factor: float.outline_width * 0.01,
+        outline_color_factor: rgb(&vector.outline_color),
+        outline_lighting_mix_factor: if keywords.outline_color_mixed == Some(true) {
+            1.0
+        } else {
+            0.0
+        },
+    }
+}
+
+#[test]
+fn test_turn_matrix_around() {
+    // A translation by (1, 2, 3) should become one by (-1, 2, -3).
+    let mut matrix = [
+        1.0, 0.0, 0.0, 0.0, //
+        0.0, 1.0, 0.0, 0.0, //
+        0.0, 0.0, 1.0, 0.0, //
+        1.0, 2.0, 3.0, 1.0,
+    ];
+
+    turn_matrix_around(&mut matrix);
+
+    assert_eq!(&matrix[12..], &[-1.0, 2.0, -3.0, 1.0]);
+    assert_eq!(matrix[0], 1.0);
+}
+
+#[test]
+fn test_unity_to_vrm1() {
+    // In front of and left of the head in Unity, where avatars face +Z and left is -X.
+    let offset = vrm0::Vec3 {
+        x: -0.02,
+        y: 0.1,
+        z: 0.05,
+    };
+
+    // VRM 1.0 avatars face +Z too, with left at +X.
+    assert_eq!(unity_to_vrm1(&offset), [0.02, 0.1, 0.05]);
+}
+
+#[test]
+fn test_convert_vrm0_to_vrm1() {
+    let mut glb = GlbFile {
+        json: json!({
+            "asset": { "version": "2.0" },
+            "extensionsUsed": ["VRM"],
+            "nodes": [
+                { "name": "Hips", "translation": [1.0, 2.0, 3.0], "children": [1] },
+                { "name": "Hair", "children": [2, 4] },
+                { "name": "HairA", "children": [3] },
+                { "name": "HairA_end" },
+                { "name": "HairB", "translation": [0.0, -0.1, 0.0] },
+                { "name": "Face", "mesh": 0 },
+            ],
+            "materials": [{ "name": "Skin" }],
+            "extensions": { "VRM": {
+                "humanoid": { "humanBones": [
+                    { "bone": "hips", "node": 0 },
+                    { "bone": "leftThumbProximal", "node": 1 },
+                ] },
+                "blendShapeMaster": { "blendShapeGroups": [
+                    { "name": "A", "presetName": "a", "binds": [{ "mesh": 0, "index": 0, "weight": 50 }] },
+                    { "name": "Smirk", "binds": [{ "mesh": 0, "index": 1 }] },
+                ] },
+                // Overlapping roots, as some exporters write them, and one past the nodes.
+                "secondaryAnimation": { "boneGroups": [{ "bones": [1, 2, 42] }] },
+                "materialProperties": [{
+                    "name": "Skin",
+                    "shader": "VRM/MToon",
+                    "floatProperties": { "_ShadeShift": -0.2, "_ShadeToony": 0.5 },
+                    "vectorProperties": { "_ShadeColor": [0.5, 0.25, 0.125, 1.0] },
+                }],
+            } },
+        }),
+        bin: vec![],
+    };
+    let position = glb.push_accessor(&[1.0, 2.0, 3.0], "VEC3", 3);
+
+    // A sparse morph target moving the only vertex, without a buffer view of its own.
+    let values = glb.push_accessor(&[0.5, 0.0, 0.25], "VEC3", 3);
+    let values = glb.json["accessors"][values]["bufferView"].clone();
+    let indices = glb.bin.len();
+    glb.bin.extend_from_slice(&0u32.to_le_bytes());
+    let indices = push(
+        &mut glb.json,
+        "bufferViews",
+        json!({ "buffer": 0, "byteOffset": indices, "byteLength": 4 }),
+    );
+    let target = push(
+        &mut glb.json,
+        "accessors",
+        json!({
+            "componentType": 5126,
+            "count": 1,
+            "type": "VEC3",
+            "sparse": {
+                "count": 1,
+                "indices": { "bufferView": indices, "componentType": 5125 },
+                "values": { "bufferView": values },
+            },
+        }),
+    );
+    glb.json["meshes"] = json!([{ "primitives": [{
+        "attributes": { "POSITION": position },
+        "targets": [{ "POSITION": target }],
+    }] }]);
+
+    let converted = convert_vrm0_to_vrm1(&glb.to_vec().unwrap()).unwrap();
+    let converted = GlbFile::from_slice(&converted).unwrap();
+    let json = &converted.json;
+
+    // Turned around.
+    assert_eq!(json["nodes"][0]["translation"], json!([-1.0, 2.0, -3.0]));
+    let layout = converted.float_accessor(position).unwrap();
+    assert_eq!(converted.read_floats(layout), vec![-1.0, 2.0, -3.0]);
+    assert_eq!(json["accessors"][position]["min"], json!([-1.0, 2.0, -3.0]));
+    let layout = converted.float_accessor(target).unwrap();
+    assert_eq!(converted.read_floats(layout), vec![-0.5, 0.0, -0.25]);
+
+    assert!(json["extensions"].get("VRM").is_none());
+    assert!(!json["extensionsUsed"]
+        .as_array()
+        .unwrap()
+        .contains(&json!("VRM")));
+
+    let vrm = &json["extensions"]["VRMC_vrm"];
+    let bones = &vrm["humanoid"]["humanBones"];
+    assert_eq!(bones["hips"]["node"], 0);
+    assert_eq!(bones["leftThumbMetacarpal"]["node"], 1);
+    assert!(bones.get("leftThumbProximal").is_none());
+
+    let expressions = &vrm["expressions"];
+    assert_eq!(
+        expressions["preset"]["aa"]["morphTargetBinds"],
+        json!([{ "node": 5, "index": 0, "weight": 0.5 }])
+    );
+    assert_eq!(
+        expressions["custom"]["Smirk"]["morphTargetBinds"][0]["weight"],
+        1.0
+    );
+
+    // Each joint once, the second branch in its own spring, with tails after the leaves.
+    let springs: Vec<Vec<u64>> = json["extensions"]["VRMC_springBone"]["springs"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|spring| {
+            let joints = spring["joints"].as_array().unwrap();
+            joints
+                .iter()
+                .map(|joint| joint["node"].as_u64().unwrap())
+                .collect()
+        })
+        .collect();
+    assert_eq!(springs, vec![vec![1, 2, 3, 6], vec![4, 7]]);
+    assert!(springs.iter().all(|joints| joints.len() >= 2));
+    assert_eq!(json["nodes"][3]["children"], json!([6]));
+    assert_eq!(json["nodes"][4]["children"], json!([7]));
+    // Straight down, as HairB points down from Hair after turning around too.
+    let tail = &json["nodes"][7]["translation"];
+    for (component, expected) in [0.0, -0.07, 0.0].into_iter().enumerate() {
+        assert!((tail[component].as_f64().unwrap() - expected).abs() < 1e-6);
+    }
+
+    let mtoon = &json["materials"][0]["extensions"]["VRMC_materials_mtoon"];
+    let factor = |key: &str| mtoon[key].as_f64().unwrap() as f32;
+    assert!((factor("shadingToonyFactor") - 0.7).abs() < 1e-6);
+    assert!((factor("shadingShiftFactor") + 0.1).abs() < 1e-6);
+    // Linear, from sRGB.
+    let shade_color: Vec<f32> = mtoon["shadeColorFactor"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|value| value.as_f64().unwrap() as f32)
+        .collect();
+    for (actual, expected) in shade_color.iter().zip([0.21404, 0.05088, 0.01435]) {
+        assert!((actual - expected).abs() < 1e-4);
+    }
+}
@@ -1,3 +1,9 @@
+use gltf::Gltf;
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::fmt;
+use std::path::Path;
+
 pub struct GltfExtensions;
 
 impl gltf::json::CustomExtensions for GltfExtensions {
@@ -10,10 +16,149 @@ pub struct RootExtensions {
     pub vrm: Option<Vrm>,
 }
 
-#[derive(serde::Deserialize, serde::Serialize, Debug, gltf::derive::Validate, Clone)]
+/// Anything that can go wrong while reading the VRM extension of a glTF file.
+#[derive(Debug)]
+pub enum VrmError {
+    Io(std::io::Error),
+    Gltf(gltf::Error),
+    /// The glTF JSON chunk itself is malformed.
+    Json(serde_json::Error),
+    /// The file is a valid glTF but has no `extensions.VRM` object.
+    MissingExtension,
+    /// A field of the VRM extension has an unexpected type or value.
+    InvalidField {
+        /// JSON path of the offending field, e.g. `extensions.VRM.humanoid.humanBones[3].node`.
+        path: String,
+        source: serde_json::Error,
+    },
+    /// An index in the glTF JSON points past the array it indexes, or at something else than an
+    /// object, e.g. `nodes[3].mesh`.
+    InvalidIndex {
+        path: String,
+    },
+    /// An accessor a tool has to rewrite isn't floats in the binary chunk, e.g. normalized
+    /// integers, so it can't be.
+    UnsupportedAccessor {
+        index: usize,
+    },
+    /// An animation to add would have no channels, which glTF doesn't allow.
+    EmptyAnimation,
+}
+
+impl fmt::Display for VrmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VrmError::Io(error) => write!(f, "failed to read file: {error}"),
+            VrmError::Gltf(error) => write!(f, "invalid glTF: {error}"),
+            VrmError::Json(error) => write!(f, "invalid glTF JSON: {error}"),
+            VrmError::MissingExtension => write!(f, "no VRM extension found"),
+            VrmError::InvalidField { path, source } => {
+                write!(f, "invalid VRM field `{path}`: {source}")
+            }
+            VrmError::InvalidIndex { path } => write!(f, "invalid glTF index `{path}`"),
+            VrmError::UnsupportedAccessor { index } => {
+                write!(f, "accessor {index} isn't floats in the binary chunk")
+            }
+            VrmError::EmptyAnimation => write!(f, "nothing to animate"),
+        }
+    }
+}
+
+impl std::error::Error for VrmError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            VrmError::Io(error) => Some(error),
+            VrmError::Gltf(error) => Some(error),
+            VrmError::Json(error) => Some(error),
+            VrmError::MissingExtension
+            | VrmError::InvalidIndex { .. }
+            | VrmError::UnsupportedAccessor { .. }
+            | VrmError::EmptyAnimation => None,
+            VrmError::InvalidField { source, .. } => Some(source),
+        }
+    }
+}
+
+/// A glTF document together with its parsed VRM extension.
+pub struct VrmDocument {
+    pub gltf: Gltf<GltfExtensions>,
+    pub vrm: Vrm,
+}
+
+impl VrmDocument {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, VrmError> {
+        let bytes = std::fs::read(path).map_err(VrmError::Io)?;
+        Self::from_slice(&bytes)
+    }
+
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, VrmError> {
+        // Parse the extension on its own first, so a bad field is reported with its path
+        // instead of as a line/column somewhere in the whole document.
+        let vrm = read_vrm(bytes)?;
+        let gltf = Gltf::from_slice(bytes).map_err(VrmError::Gltf)?;
+
+        Ok(VrmDocument { gltf, vrm })
+    }
+}
+
+/// Returns the JSON chunk of a `.glb`/`.vrm` file, or the input itself for a `.gltf` file.
+pub fn json_chunk(bytes: &[u8]) -> Result<Cow<[u8]>, VrmError> {
+    if bytes.starts_with(b"glTF") {
+        let glb = gltf::Glb::from_slice(bytes).map_err(VrmError::Gltf)?;
+        Ok(glb.json)
+    } else {
+        Ok(Cow::Borrowed(bytes))
+    }
+}
+
+/// Reads only the VRM extension of a glTF file.
+pub fn read_vrm(bytes: &[u8]) -> Result<Vrm, VrmError> {
+    let json = json_chunk(bytes)?;
+    let root: serde_json::Value = serde_json::from_slice(&json).map_err(VrmError::Json)?;
+
+    let extension = root
+        .pointer("/extensions/VRM")
+        .ok_or(VrmError::MissingExtension)?;
+
+    serde_path_to_error::deserialize(extension).map_err(|error| {
+        let path = match error.path().to_string().as_str() {
+            "." => "extensions.VRM".to_string(),
+            path => format!("extensions.VRM.{path}"),
+        };
+
+        VrmError::InvalidField {
+            path,
+            source: error.into_inner(),
+        }
+    })
+}
+
+/// VRM 0.x writes `-1` for "no index", which doesn't fit in a glTF index.
+fn deserialize_optional_index<'de, D>(deserializer: D) -> Result<Option<u32>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let index = Option::<i64>::deserialize(deserializer)?;
+    Ok(index.and_then(|index| u32::try_from(index).ok()))
+}
+
+fn serialize_optional_index<S>(index: &Option<u32>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    match index {
+        Some(index) => serializer.serialize_i64(*index as i64),
+        None => serializer.serialize_i64(-1),
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, gltf::derive::Validate, Clone, Default)]
+#[serde(default)]
 pub struct Vrm {
     #[serde(rename = "exporterVersion")]
     pub exporter_version: String,
+    #[serde(rename = "specVersion")]
+    pub spec_version: String,
     pub meta: Meta,
     pub humanoid: Humanoid,
     #[serde(rename = "firstPerson")]
@@ -27,6 +172,7 @@ pub struct Vrm {
 }
 
 #[derive(serde::Deserialize, serde::Serialize, Debug, gltf::derive::Validate, Clone)]
+#[serde(default)]
 pub struct Meta {
     pub version: String,
     pub author: String,
@@ -34,7 +180,12 @@ pub struct Meta {
     pub contact_information: String,
     pub reference: String,
     pub title: String,
-    pub texture: u32,
+    /// Index of the thumbnail texture.
+    #[serde(
+        deserialize_with = "deserialize_optional_index",
+        serialize_with = "serialize_optional_index"
+    )]
+    pub texture: Option<u32>,
     #[serde(rename = "allowedUserName")]
     pub allowed_user_name: String,
     #[serde(rename = "violentUssageName")]
@@ -52,7 +203,73 @@ pub struct Meta {
     pub other_license_url: String,
 }
 
+impl Default for Meta {
+    /// The most restrictive permissions, as the spec says to assume when they are missing.
+    fn default() -> Self {
+        Meta {
+            version: String::new(),
+            author: String::new(),
+            contact_information: String::new(),
+            reference: String::new(),
+            title: String::new(),
+            texture: None,
+            allowed_user_name: "OnlyAuthor".to_string(),
+            violent_usage_name: "Disallow".to_string(),
+            sexual_usage_name: "Disallow".to_string(),
+            commercial_usage_name: "Disallow".to_string(),
+            other_permission_url: String::new(),
+            license_name: "Redistribution_Prohibited".to_string(),
+            other_license_url: String::new(),
+        }
+    }
+}
+
+/// Who may perform as the avatar.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AllowedUser {
+    OnlyAuthor,
+    ExplicitlyLicensedPerson,
+    Everyone,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Usage {
+    Allow,
+    Disallow,
+}
+
+impl Meta {
+    /// Unknown values are treated as the most restrictive one.
+    pub fn allowed_user(&self) -> AllowedUser {
+        match self.allowed_user_name.as_str() {
+            "Everyone" => AllowedUser::Everyone,
+            "ExplicitlyLicensedPerson" => AllowedUser::ExplicitlyLicensedPerson,
+            _ => AllowedUser::OnlyAuthor,
+        }
+    }
+
+    pub fn violent_usage(&self) -> Usage {
+        Self::usage(&self.violent_usage_name)
+    }
+
+    pub fn sexual_usage(&self) -> Usage {
+        Self::usage(&self.sexual_usage_name)
+    }
+
+    pub fn commercial_usage(&self) -> Usage {
+        Self::usage(&self.commercial_usage_name)
+    }
+
+    fn usage(name: &str) -> Usage {
+        match name {
+            "Allow" => Usage::Allow,
+            _ => Usage::Disallow,
+        }
+    }
+}
+
 #[derive(serde::Deserialize, serde::Serialize, Debug, gltf::derive::Validate, Clone)]
+#[serde(default)]
 pub struct Humanoid {
     #[serde(rename = "humanBones")]
     pub human_bones: Vec<Bone>,
@@ -74,24 +291,48 @@ pub struct Humanoid {
     pub has_translation_dof: bool,
 }
 
+impl Default for Humanoid {
+    fn default() -> Self {
+        Humanoid {
+            human_bones: vec![],
+            arm_stretch: 0.05,
+            leg_stretch: 0.05,
+            upper_arm_twist: 0.5,
+            lower_arm_twist: 0.5,
+            upper_leg_twist: 0.5,
+            lower_leg_twist: 0.5,
+            feet_spacing: 0.0,
+            has_translation_dof: false,
+        }
+    }
+}
+
 #[derive(serde::Deserialize, serde::Serialize, Debug, gltf::derive::Validate, Clone)]
 pub struct Bone {
     #[serde(rename = "bone")]
     pub name: String,
     pub node: u32,
-    #[serde(rename = "useDefaultValues")]
+    #[serde(default = "default_true", rename = "useDefaultValues")]
     pub use_default_values: bool,
 }
 
+fn default_true() -> bool {
+    true
+}
+
 #[derive(serde::Deserialize, serde::Serialize, Debug, gltf::derive::Validate, Clone)]
+#[serde(default)]
 pub struct FirstPerson {
-    #[serde(rename = "firstPersonBone")]
-    pub first_person_bone: u32,
+    #[serde(
+        rename = "firstPersonBone",
+        deserialize_with = "deserialize_optional_index",
+        serialize_with = "serialize_optional_index"
+    )]
+    pub first_person_bone: Option<u32>,
     #[serde(rename = "firstPersonBoneOffset")]
     pub first_person_bone_offset: Vec3,
-    // todo
     #[serde(rename = "meshAnnotations")]
-    pub mesh_annotations: Vec<()>,
+    pub mesh_annotations: Vec<MeshAnnotation>,
     #[serde(rename = "lookAtTypeName")]
     pub look_at_type_name: String,
     #[serde(rename = "lookAtHorizontalInner")]
@@ -104,42 +345,112 @@ pub struct FirstPerson {
     pub look_at_vertical_up: LookAtCurve,
 }
 
+impl Default for FirstPerson {
+    fn default() -> Self {
+        FirstPerson {
+            first_person_bone: None,
+            first_person_bone_offset: Vec3::default(),
+            mesh_annotations: vec![],
+            look_at_type_name: "Bone".to_string(),
+            look_at_horizontal_inner: LookAtCurve::default(),
+            look_at_horizontal_outer: LookAtCurve::default(),
+            look_at_vertical_down: LookAtCurve::default(),
+            look_at_vertical_up: LookAtCurve::default(),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, gltf::derive::Validate, Clone)]
+pub struct MeshAnnotation {
+    pub mesh: u32,
+    #[serde(default = "default_first_person_flag", rename = "firstPersonFlag")]
+    pub first_person_flag: String,
+}
+
+fn default_first_person_flag() -> String {
+    "Auto".to_string()
+}
+
 #[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+#[serde(default)]
 pub struct LookAtCurve {
-    pub curve: [u32; 8],
+    pub curve: [f32; 8],
     #[serde(rename = "xRange")]
-    pub x_range: u32,
+    pub x_range: f32,
     #[serde(rename = "yRange")]
-    pub y_range: u32,
+    pub y_range: f32,
+}
+
+impl Default for LookAtCurve {
+    fn default() -> Self {
+        LookAtCurve {
+            curve: [0.0, 0.0, 0.0, 1.0, 1.0, 1.0, 1.0, 0.0],
+            x_range: 90.0,
+            y_range: 10.0,
+        }
+    }
 }
 
 impl gltf::json::validation::Validate for LookAtCurve {}
 
-#[derive(serde::Deserialize, serde::Serialize, Debug, gltf::derive::Validate, Clone)]
+#[derive(serde::Deserialize, serde::Serialize, Debug, gltf::derive::Validate, Clone, Default)]
+#[serde(default)]
 pub struct BlendShapeMaster {
     #[serde(rename = "blendShapeGroups")]
     pub blend_shape_groups: Vec<BlendShapeGroup>,
 }
 
 #[derive(serde::Deserialize, serde::Serialize, Debug, gltf::derive::Validate, Clone)]
+#[serde(default)]
 pub struct BlendShapeGroup {
     pub name: String,
     #[serde(rename = "presetName")]
     pub preset_name: String,
     pub binds: Vec<Bind>,
-    // todo
     #[serde(rename = "materialValues")]
-    pub material_values: Vec<()>,
+    pub material_values: Vec<MaterialValue>,
+    #[serde(rename = "isBinary")]
+    pub is_binary: bool,
+}
+
+impl Default for BlendShapeGroup {
+    fn default() -> Self {
+        BlendShapeGroup {
+            name: String::new(),
+            preset_name: "unknown".to_string(),
+            binds: vec![],
+            material_values: vec![],
+            is_binary: false,
+        }
+    }
 }
 
 #[derive(serde::Deserialize, serde::Serialize, Debug, gltf::derive::Validate, Clone)]
 pub struct Bind {
     pub mesh: u32,
     pub index: u32,
-    pub weight: u32,
+    /// In the range of 0 to 100.
+    #[serde(default = "default_bind_weight")]
+    pub weight: f32,
 }
 
-#[derive(serde::Deserialize, serde::Serialize, Debug, gltf::derive::Validate, Clone)]
+fn default_bind_weight() -> f32 {
+    100.0
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, gltf::derive::Validate, Clone, Default)]
+#[serde(default)]
+pub struct MaterialValue {
+    #[serde(rename = "materialName")]
+    pub material_name: String,
+    #[serde(rename = "propertyName")]
+    pub property_name: String,
+    #[serde(rename = "targetValue")]
+    pub target_value: Vec<f32>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, gltf::derive::Validate, Clone, Default)]
+#[serde(default)]
 pub struct SecondaryAnimation {
     #[serde(rename = "boneGroups")]
     pub bone_groups: Vec<BoneGroup>,
@@ -148,6 +459,7 @@ pub struct SecondaryAnimation {
 }
 
 #[derive(serde::Deserialize, serde::Serialize, Debug, gltf::derive::Validate, Clone)]
+#[serde(default)]
 pub struct BoneGroup {
     pub comment: String,
     pub stiffiness: f32,
@@ -157,7 +469,12 @@ pub struct BoneGroup {
     pub gravity_dir: Vec3,
     #[serde(rename = "dragForce")]
     pub drag_force: f32,
-    pub center: f32,
+    /// Node the spring simulation is relative to.
+    #[serde(
+        deserialize_with = "deserialize_optional_index",
+        serialize_with = "serialize_optional_index"
+    )]
+    pub center: Option<u32>,
     #[serde(rename = "hitRadius")]
     pub hit_radius: f32,
     pub bones: Vec<u32>,
@@ -165,19 +482,42 @@ pub struct BoneGroup {
     pub collider_groups: Vec<u32>,
 }
 
+impl Default for BoneGroup {
+    fn default() -> Self {
+        BoneGroup {
+            comment: String::new(),
+            stiffiness: 1.0,
+            gravity_power: 0.0,
+            gravity_dir: Vec3 {
+                x: 0.0,
+                y: -1.0,
+                z: 0.0,
+            },
+            drag_force: 0.4,
+            center: None,
+            hit_radius: 0.02,
+            bones: vec![],
+            collider_groups: vec![],
+        }
+    }
+}
+
 #[derive(serde::Deserialize, serde::Serialize, Debug, gltf::derive::Validate, Clone)]
 pub struct ColliderGroup {
     pub node: u32,
+    #[serde(default)]
     pub colliders: Vec<Collider>,
 }
 
-#[derive(serde::Deserialize, serde::Serialize, Debug, gltf::derive::Validate, Clone)]
+#[derive(serde::Deserialize, serde::Serialize, Debug, gltf::derive::Validate, Clone, Default)]
+#[serde(default)]
 pub struct Collider {
     pub offset: Vec3,
     pub radius: f32,
 }
 
-#[derive(serde::Deserialize, serde::Serialize, Debug, gltf::derive::Validate, Clone)]
+#[derive(serde::Deserialize, serde::Serialize, Debug, gltf::derive::Validate, Clone, Default)]
+#[serde(default)]
 pub struct Vec3 {
     pub x: f32,
     pub y: f32,
@@ -191,10 +531,11 @@ impl Vec3 {
 }
 
 #[derive(serde::Deserialize, serde::Serialize, Debug, gltf::derive::Validate, Clone)]
+#[serde(default)]
 pub struct MaterialProperty {
     pub name: String,
     #[serde(rename = "renderQueue")]
-    pub render_queue: u32,
+    pub render_queue: i32,
     pub shader: String,
     #[serde(rename = "floatProperties")]
     pub float: FloatProperties,
@@ -208,7 +549,24 @@ pub struct MaterialProperty {
     pub tag_map: TagMap,
 }
 
+impl Default for MaterialProperty {
+    fn default() -> Self {
+        MaterialProperty {
+            name: String::new(),
+            render_queue: 2000,
+            shader: "VRM_USE_GLTFSHADER".to_string(),
+            float: FloatProperties::default(),
+            vector: VectorProperties::default(),
+            texture: TextureProperties::default(),
+            keyword_map: KeywordMap::default(),
+            tag_map: TagMap::default(),
+        }
+    }
+}
+
+/// Defaults are the ones of the MToon shader.
 #[derive(serde::Deserialize, serde::Serialize, Debug, gltf::derive::Validate, Clone)]
+#[serde(default)]
 pub struct FloatProperties {
     #[serde(rename = "_ShadeShift")]
     pub shade_shift: f32,
@@ -222,21 +580,36 @@ pub struct FloatProperties {
     pub outline_width: f32,
 }
 
-#[derive(serde::Deserialize, serde::Serialize, Debug, gltf::derive::Validate, Clone)]
+impl Default for FloatProperties {
+    fn default() -> Self {
+        FloatProperties {
+            shade_shift: 0.0,
+            shade_toony: 0.9,
+            cutoff: 0.5,
+            indirect_light_insensity: 0.1,
+            outline_width: 0.5,
+        }
+    }
+}
+
+/// Texture indices. Materials only list the textures they actually use.
+#[derive(serde::Deserialize, serde::Serialize, Debug, gltf::derive::Validate, Clone, Default)]
+#[serde(default)]
 pub struct TextureProperties {
-    #[serde(rename = "_MainTex")]
-    pub main_tex: u32,
-    #[serde(rename = "_ShadeTexture")]
-    pub shade_texture: u32,
-    #[serde(rename = "_BumpMap")]
-    pub bump_map: u32,
-    #[serde(rename = "_SphereAdd")]
-    pub sphere_add: u32,
-    #[serde(rename = "_EmissionMap")]
-    pub emission_map: u32,
+    #[serde(rename = "_MainTex", skip_serializing_if = "Option::is_none")]
+    pub main_tex: Option<u32>,
+    #[serde(rename = "_ShadeTexture", skip_serializing_if = "Option::is_none")]
+    pub shade_texture: Option<u32>,
+    #[serde(rename = "_BumpMap", skip_serializing_if = "Option::is_none")]
+    pub bump_map: Option<u32>,
+    #[serde(rename = "_SphereAdd", skip_serializing_if = "Option::is_none")]
+    pub sphere_add: Option<u32>,
+    #[serde(rename = "_EmissionMap", skip_serializing_if = "Option::is_none")]
+    pub emission_map: Option<u32>,
 }
 
 #[derive(serde::Deserialize, serde::Serialize, Debug, gltf::derive::Validate, Clone)]
+#[serde(default)]
 pub struct VectorProperties {
     #[serde(rename = "_Color")]
     pub color: [f32; 4],
@@ -246,22 +619,35 @@ pub struct VectorProperties {
     pub outline_color: [f32; 4],
 }
 
-#[derive(serde::Deserialize, serde::Serialize, Debug, gltf::derive::Validate, Clone)]
+impl Default for VectorProperties {
+    fn default() -> Self {
+        VectorProperties {
+            color: [1.0, 1.0, 1.0, 1.0],
+            shade_color: [0.97, 0.81, 0.86, 1.0],
+            outline_color: [0.0, 0.0, 0.0, 1.0],
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, gltf::derive::Validate, Clone, Default)]
+#[serde(default)]
 pub struct TagMap {
     #[serde(rename = "RenderType")]
     pub render_type: RenderType,
 }
 
-#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+#[derive(Clone, Debug, Default, serde::Deserialize, serde::Serialize)]
 pub enum RenderType {
     Transparent,
     TransparentCutout,
+    #[default]
     Opaque,
 }
 
 impl gltf::json::validation::Validate for RenderType {}
 
-#[derive(serde::Deserialize, serde::Serialize, Debug, gltf::derive::Validate, Clone)]
+#[derive(serde::Deserialize, serde::Serialize, Debug, gltf::derive::Validate, Clone, Default)]
+#[serde(default)]
 pub struct KeywordMap {
     #[serde(rename = "_ALPHABLEND_ON")]
     pub alpha_blend: Option<bool>,
@@ -275,6 +661,8 @@ pub struct KeywordMap {
     pub outline_color_mixed: Option<bool>,
     #[serde(rename = "MTOON_OUTLINE_WIDTH_WORLD")]
     pub outline_width_world: Option<bool>,
+    #[serde(rename = "MTOON_OUTLINE_WIDTH_SCREEN")]
+    pub outline_width_screen: Option<bool>,
 }
 
 #[test]
@@ -289,3 +677,42 @@ fn test() {
 
     let vrm = extensions.custom.vrm.as_ref().unwrap();
 }
+
+#[test]
+fn test_missing_fields_use_defaults() {
+    let json = br#"{
+        "asset": { "version": "2.0" },
+        "extensions": {
+            "VRM": {
+                "meta": { "title": "Minimal", "texture": -1 },
+                "secondaryAnimation": { "boneGroups": [{ "bones": [1, 2], "center": -1 }] }
+            }
+        }
+    }"#;
+
+    let vrm = read_vrm(json).unwrap();
+
+    assert_eq!(vrm.meta.title, "Minimal");
+    assert_eq!(vrm.meta.texture, None);
+    assert_eq!(vrm.meta.commercial_usage_name, "Disallow");
+    assert_eq!(vrm.humanoid.arm_stretch, 0.05);
+    assert_eq!(vrm.secondary_animation.bone_groups[0].center, None);
+    assert_eq!(vrm.secondary_animation.bone_groups[0].drag_force, 0.4);
+}
+
+#[test]
+fn test_invalid_field_reports_path() {
+    let json = br#"{
+        "asset": { "version": "2.0" },
+        "extensions": {
+            "VRM": { "humanoid": { "humanBones": [{ "bone": "hips", "node": "zero" }] } }
+        }
+    }"#;
+
+    match read_vrm(json) {
+        Err(VrmError::InvalidField { path, .. }) => {
+            assert_eq!(path, "extensions.VRM.humanoid.humanBones[0].node");
+        }
+        other => panic!("Unexpected result: {:?}", other.map(|_| ())),
+    }
+}
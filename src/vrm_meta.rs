@@ -0,0 +1,231 @@
+//! Shows the meta information of loaded VRM avatars and checks their usage permissions.
+//!
+//! Set the [`UsagePolicy`] resource to describe how the application uses avatars.
+//! Avatars whose permissions don't allow that use are either refused at load time or
+//! flagged in the panel, see [`ViolationAction`].
+
+use crate::morph_targets::VrmData;
+use crate::vrm_gltf::{AllowedUser, Meta, Usage};
+use bevy::prelude::*;
+use bevy_inspector_egui::bevy_egui::{egui, EguiContexts};
+use std::fmt;
+
+/// What to do with an avatar whose permissions don't match the [`UsagePolicy`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ViolationAction {
+    /// Load the avatar anyway and list the violations in the panel.
+    Warn,
+    /// Don't spawn the avatar.
+    Refuse,
+}
+
+/// How the application is going to use the avatars it loads.
+#[derive(Resource, Clone, Debug)]
+pub struct UsagePolicy {
+    /// The user is not the author of the avatar.
+    pub third_party: bool,
+    /// The user holds a separate license from the author.
+    pub licensed: bool,
+    pub commercial: bool,
+    pub violent: bool,
+    pub sexual: bool,
+    pub on_violation: ViolationAction,
+}
+
+impl Default for UsagePolicy {
+    fn default() -> Self {
+        UsagePolicy {
+            third_party: true,
+            licensed: false,
+            commercial: false,
+            violent: false,
+            sexual: false,
+            on_violation: ViolationAction::Warn,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PermissionViolation {
+    OnlyAuthor,
+    LicenseRequired,
+    Commercial,
+    Violent,
+    Sexual,
+}
+
+impl fmt::Display for PermissionViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PermissionViolation::OnlyAuthor => write!(f, "Only the author may use this avatar"),
+            PermissionViolation::LicenseRequired => {
+                write!(f, "A separate license from the author is required")
+            }
+            PermissionViolation::Commercial => write!(f, "Commercial use is not allowed"),
+            PermissionViolation::Violent => write!(f, "Violent use is not allowed"),
+            PermissionViolation::Sexual => write!(f, "Sexual use is not allowed"),
+        }
+    }
+}
+
+/// Lists everything the policy wants to do that the avatar doesn't allow.
+pub fn check_permissions(meta: &Meta, policy: &UsagePolicy) -> Vec<PermissionViolation> {
+    let mut violations = vec![];
+
+    if policy.third_party {
+        match meta.allowed_user() {
+            AllowedUser::OnlyAuthor => violations.push(PermissionViolation::OnlyAuthor),
+            AllowedUser::ExplicitlyLicensedPerson if !policy.licensed => {
+                violations.push(PermissionViolation::LicenseRequired)
+            }
+            _ => {}
+        }
+    }
+
+    if policy.commercial && meta.commercial_usage() == Usage::Disallow {
+        violations.push(PermissionViolation::Commercial);
+    }
+    if policy.violent && meta.violent_usage() == Usage::Disallow {
+        violations.push(PermissionViolation::Violent);
+    }
+    if policy.sexual && meta.sexual_usage() == Usage::Disallow {
+        violations.push(PermissionViolation::Sexual);
+    }
+
+    violations
+}
+
+fn show_meta_panel(
+    mut contexts: EguiContexts,
+    policy: Res<UsagePolicy>,
+    vrm_query: Query<(Entity, &VrmData)>,
+    images: Res<Assets<Image>>,
+) {
+    if vrm_query.is_empty() {
+        return;
+    }
+
+    // Register the thumbnails with egui before borrowing its context.
+    let thumbnails: Vec<_> = vrm_query
+        .iter()
+        .map(|(_, vrm)| {
+            let thumbnail = vrm.thumbnail.as_ref()?;
+            let size = images.get(thumbnail)?.size_f32();
+            let texture_id = contexts.add_image(thumbnail.clone_weak());
+            // Fit into a 128 px square.
+            let scale = 128.0 / size.max_element();
+            Some((texture_id, egui::vec2(size.x * scale, size.y * scale)))
+        })
+        .collect();
+
+    egui::Window::new("Avatar Info").show(contexts.ctx_mut(), |ui| {
+        for ((entity, vrm), thumbnail) in vrm_query.iter().zip(thumbnails) {
+            let meta = &vrm.meta;
+
+            let title = if meta.title.is_empty() {
+                format!("{:?}", entity)
+            } else {
+                meta.title.clone()
+            };
+
+            ui.collapsing(title, |ui| {
+                if let Some(thumbnail) = thumbnail {
+                    ui.image(thumbnail);
+                }
+
+                egui::Grid::new(entity).num_columns(2).show(ui, |ui| {
+                    ui.label("Author");
+                    ui.label(&meta.author);
+                    ui.end_row();
+
+                    ui.label("Version");
+                    ui.label(&meta.version);
+                    ui.end_row();
+
+                    ui.label("Contact");
+                    ui.label(&meta.contact_information);
+                    ui.end_row();
+
+                    ui.label("Reference");
+                    ui.label(&meta.reference);
+                    ui.end_row();
+
+                    ui.label("Allowed user");
+                    ui.label(&meta.allowed_user_name);
+                    ui.end_row();
+
+                    ui.label("Violent usage");
+                    ui.label(&meta.violent_usage_name);
+                    ui.end_row();
+
+                    ui.label("Sexual usage");
+                    ui.label(&meta.sexual_usage_name);
+                    ui.end_row();
+
+                    ui.label("Commercial usage");
+                    ui.label(&meta.commercial_usage_name);
+                    ui.end_row();
+
+                    ui.label("License");
+                    if meta.other_license_url.is_empty() {
+                        ui.label(&meta.license_name);
+                    } else {
+                        ui.hyperlink_to(&meta.license_name, &meta.other_license_url);
+                    }
+                    ui.end_row();
+
+                    if !meta.other_permission_url.is_empty() {
+                        ui.label("Other permissions");
+                        ui.hyperlink(&meta.other_permission_url);
+                        ui.end_row();
+                    }
+                });
+
+                let violations = check_permissions(meta, &policy);
+                if violations.is_empty() {
+                    ui.colored_label(egui::Color32::GREEN, "Allowed for this application");
+                } else {
+                    for violation in violations {
+                        ui.colored_label(egui::Color32::YELLOW, violation.to_string());
+                    }
+                }
+            });
+        }
+    });
+}
+
+pub struct VrmMetaPlugin;
+
+impl Plugin for VrmMetaPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<UsagePolicy>()
+            .add_systems(Update, show_meta_panel);
+    }
+}
+
+#[test]
+fn test_permissions() {
+    let meta = Meta {
+        allowed_user_name: "Everyone".to_string(),
+        commercial_usage_name: "Disallow".to_string(),
+        violent_usage_name: "Allow".to_string(),
+        ..default()
+    };
+
+    let policy = UsagePolicy {
+        commercial: true,
+        violent: true,
+        ..default()
+    };
+
+    assert_eq!(
+        check_permissions(&meta, &policy),
+        vec![PermissionViolation::Commercial]
+    );
+
+    // Missing permissions default to the most restrictive ones.
+    assert_eq!(
+        check_permissions(&Meta::default(), &UsagePolicy::default()),
+        vec![PermissionViolation::OnlyAuthor]
+    );
+}